@@ -1,15 +1,22 @@
 #include "Nes_Apu.h"
-#include "Sound_Queue.h"
+#include "Nes_Vrc6.h"
+#include "Nes_Namco.h"
 #include "apu_snapshot.h"
 #include "common.hpp"
-#include <iostream>
 Nes_Apu apu;
 Blip_Buffer buf;
-Sound_Queue* soundQueue;
-
-const int OUT_SIZE = 4096;
-blip_sample_t outBuf[OUT_SIZE];
+// cartridge expansion audio, fed in as amplitude changes
+Blip_Synth<blip_good_quality,32767> expansion_synth;
+// expansion chips emulated here rather than on the Rust side
+Nes_Vrc6 vrc6;
+Nes_Namco namco;
 
+// the 2A03 followed by the expansion chips
+struct snapshot_t {
+    apu_snapshot_t apu;
+    vrc6_snapshot_t vrc6;
+    namco_snapshot_t namco;
+};
 extern "C" {
 
 void apuinit(int (*callback)( void* user_data, cpu_addr_t ),void* user_data) {
@@ -18,14 +25,18 @@ void apuinit(int (*callback)( void* user_data, cpu_addr_t ),void* user_data) {
 
     apu.output(&buf);
     apu.dmc_reader(callback,user_data);
-
-    soundQueue = new Sound_Queue;
-    soundQueue->init(96000);
+    vrc6.output(&buf);
+    namco.output(&buf);
+    // the Namco 163's RAM is on the cartridge, so the reset button leaves it alone
+    namco.reset();
+    // one unit is 1/256 of a square channel volume step
+    expansion_synth.volume_unit(0.1128 / (15 * 256));
 }
 
 void apureset()
 {
     apu.reset();
+    vrc6.reset();
     buf.clear();
 }
 
@@ -38,23 +49,51 @@ u8 apuread(int elapsed) {
   return apu.read_status(elapsed);
 }
 
+void apuexpansion(int elapsed, int delta)
+{
+    expansion_synth.offset(elapsed, delta, &buf);
+}
+
+void apuvrc6_write(int elapsed, int osc, int reg, u8 v)
+{
+    vrc6.write_osc(elapsed, osc, reg, v);
+}
+
+void apunamco_write(int elapsed, int addr, u8 v)
+{
+    namco.write_addr(addr & 0x7F);
+    namco.write_data(elapsed, v);
+}
+
 void apurun_frame(int elapsed)
 {
     apu.end_frame(elapsed);
+    vrc6.end_frame(elapsed);
+    namco.end_frame(elapsed);
     buf.end_frame(elapsed);
+}
+
+int apuread_samples(blip_sample_t* out, int max)
+{
+    return buf.read_samples(out, max);
+}
 
-    if (buf.samples_avail() >= OUT_SIZE)
-        soundQueue->write(outBuf, buf.read_samples(outBuf, OUT_SIZE));
+int apusnapshot_size() {
+    return sizeof(snapshot_t);
 }
 
-apu_snapshot_t* aputake_snapshot() {
-    static apu_snapshot_t snapshot;
-    apu.save_snapshot(&snapshot);
+snapshot_t* aputake_snapshot() {
+    static snapshot_t snapshot;
+    apu.save_snapshot(&snapshot.apu);
+    vrc6.save_snapshot(&snapshot.vrc6);
+    namco.save_snapshot(&snapshot.namco);
     return &snapshot;
 }
 
-void apuget_snapshot(apu_snapshot_t* reset) {
-    apu.load_snapshot(*reset);
+void apuget_snapshot(snapshot_t* reset) {
+    apu.load_snapshot(reset->apu);
+    vrc6.load_snapshot(reset->vrc6);
+    namco.load_snapshot(reset->namco);
 }
 
 }
@@ -1,5 +1,5 @@
 #!/bin/bash
-g++ apu.cpp -Ilib/include -Isrc/include lib/*.cpp -lSDL2 -c
+g++ apu.cpp -Ilib/include -Isrc/include $(ls lib/*.cpp | grep -v Sound_Queue) -c
 ar rc libapu.a *.o
 ranlib libapu.a
 rm ../libraries/libapu.a
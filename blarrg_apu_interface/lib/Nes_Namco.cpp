@@ -58,6 +58,31 @@ BOOST::uint8_t& Nes_Namco::access()
 	return reg [addr];
 }
 
+void Nes_Namco::save_snapshot( namco_snapshot_t* out )
+{
+	out->addr = addr_reg;
+	for ( int i = 0; i < reg_count; i++ )
+		out->regs [i] = reg [i];
+	for ( int i = 0; i < osc_count; i++ )
+	{
+		out->delays [i] = oscs [i].delay;
+		out->wave_pos [i] = oscs [i].wave_pos;
+	}
+}
+
+void Nes_Namco::load_snapshot( namco_snapshot_t const& in )
+{
+	reset();
+	addr_reg = in.addr;
+	for ( int i = 0; i < reg_count; i++ )
+		reg [i] = in.regs [i];
+	for ( int i = 0; i < osc_count; i++ )
+	{
+		oscs [i].delay = in.delays [i];
+		oscs [i].wave_pos = in.wave_pos [i];
+	}
+}
+
 /*
 void Nes_Namco::reflect_state( Tagged_Data& data )
 {
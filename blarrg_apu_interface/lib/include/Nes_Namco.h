@@ -33,7 +33,6 @@ public:
 	enum { addr_reg_addr = 0xF800 };
 	void write_addr( int );
 	
-	// to do: implement save/restore
 	void save_snapshot( namco_snapshot_t* out );
 	void load_snapshot( namco_snapshot_t const& );
 	
@@ -62,6 +61,16 @@ private:
 	void run_until( cpu_time_t );
 };
 
+struct namco_snapshot_t
+{
+	BOOST::uint32_t delays [8];
+	BOOST::uint8_t regs [0x80];
+	BOOST::uint8_t addr;
+	BOOST::uint8_t unused [3];
+	BOOST::uint8_t wave_pos [8];
+};
+BOOST_STATIC_ASSERT( sizeof (namco_snapshot_t) == 172 );
+
 inline void Nes_Namco::volume( double v ) { synth.volume( 0.10 / osc_count * v ); }
 
 inline void Nes_Namco::treble_eq( const blip_eq_t& eq ) { synth.treble_eq( eq ); }
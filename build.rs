@@ -1,5 +1,10 @@
+use std::env;
+
 fn main() {
-    println!("cargo:rustc-link-search=./libraries");
+    //libapu.a is the blargg apu only; audio output is done by the sdl frontend, so the core links without sdl
+    let dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rustc-link-search={}/libraries",dir);
     println!("cargo:rustc-link-lib=apu");
     println!("cargo:rustc-link-lib=stdc++");
+    println!("cargo:rerun-if-changed=libraries/libapu.a");
 }
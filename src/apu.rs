@@ -2,12 +2,20 @@
 use cpu::CPU;
 use std::slice;
 use std::io::Write;
+
+pub const SAMPLE_RATE: i32 = 96000;
+
 extern {
     fn apuinit(read_mem: extern fn(cpu:*mut CPU,c: u32) -> i32,cpu:*mut CPU);
     fn apureset();
     fn apuwrite(elapsed:i32,addr:u16,value:u8);
     fn apuread(elapsed:i32) -> u8;
+    fn apuexpansion(elapsed:i32,delta:i32);
+    fn apuvrc6_write(elapsed:i32,osc:i32,reg:i32,value:u8);
+    fn apunamco_write(elapsed:i32,addr:i32,value:u8);
     fn apurun_frame(elapsed:i32);
+    fn apuread_samples(out:*mut i16,max:i32) -> i32;
+    fn apusnapshot_size() -> i32;
     fn aputake_snapshot() -> *const u8;
     fn apuget_snapshot(snapshot: *const u8);
 }
@@ -33,23 +41,50 @@ pub fn apu_read(elapsed:i32) -> u8 {
         apuread(elapsed)
     }
 }
+//cartridge audio level changes; one unit is 1/256 of a square channel volume step
+pub fn apu_expansion(elapsed:i32,delta:i32) {
+    unsafe {
+        apuexpansion(elapsed,delta);
+    }
+}
+//VRC6 sound registers $9000-$9002, $A000-$A002 and $B000-$B002 as osc 0-2, reg 0-2
+pub fn apu_vrc6_write(elapsed:i32,osc:i32,reg:i32,value:u8) {
+    unsafe {
+        apuvrc6_write(elapsed,osc,reg,value);
+    }
+}
+//a byte of the Namco 163's sound RAM, which the board keeps its own copy of
+pub fn apu_namco_write(elapsed:i32,addr:i32,value:u8) {
+    unsafe {
+        apunamco_write(elapsed,addr,value);
+    }
+}
 pub fn apu_run_frame(elapsed:i32) {
     unsafe {
         apurun_frame(elapsed);
     }
 }
 
+pub fn apu_read_samples(out:&mut [i16]) -> usize {
+    unsafe {
+        apuread_samples(out.as_mut_ptr(),out.len() as i32) as usize
+    }
+}
+
 pub fn apu_take_snapshot() -> Vec<u8> {
     unsafe {
         let ptr = aputake_snapshot();
         let mut v = vec![];
-        v.write(slice::from_raw_parts(ptr,72)).unwrap();
+        v.write(slice::from_raw_parts(ptr,apusnapshot_size() as usize)).unwrap();
         v
     }
 }
 
+//older states only have the 2A03; the expansion chips come back silent
 pub fn apu_get_snapshot(xs:&[u8]) {
     unsafe {
-        apuget_snapshot(xs.as_ptr());
+        let mut v = xs.to_vec();
+        v.resize(apusnapshot_size() as usize,0);
+        apuget_snapshot(v.as_ptr());
     }
 }
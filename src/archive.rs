@@ -0,0 +1,39 @@
+use ines::RomError;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+use std::io::Cursor;
+use std::io::Read;
+
+//extensions of the files worth picking out of an archive
+const ROM_EXTENSIONS: [&str;4] = [".nes",".fds",".nsf",".unf"];
+
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(b"\x1F\x8B") || data.starts_with(b"PK\x03\x04")
+}
+
+//the rom inside a .gz or .zip; a zip gives up the named entry, or the first rom in it
+pub fn extract(data: &[u8],entry: Option<String>) -> Result<Vec<u8>,RomError> {
+    let mut result = vec![];
+    if data.starts_with(b"\x1F\x8B") {
+        GzDecoder::new(data).read_to_end(&mut result)?;
+        return Ok(result);
+    }
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(|e| RomError::Archive(e.to_string()))?;
+    let index = match entry {
+        Some(name) => {
+            let found = (0..zip.len()).find(|&i| zip.by_index(i).map(|file| {
+                file.name() == name || file.name().rsplit('/').next() == Some(&name)
+            }).unwrap_or(false));
+            found.ok_or(RomError::MissingEntry(name))?
+        }
+        None => {
+            let found = (0..zip.len()).find(|&i| zip.by_index(i).map(|file| {
+                let lower = file.name().to_lowercase();
+                !file.is_dir() && ROM_EXTENSIONS.iter().any(|extension| lower.ends_with(extension))
+            }).unwrap_or(false));
+            found.ok_or(RomError::Archive("there is no .nes, .fds, .nsf or .unf file in it".to_string()))?
+        }
+    };
+    zip.by_index(index).map_err(|e| RomError::Archive(e.to_string()))?.read_to_end(&mut result)?;
+    Ok(result)
+}
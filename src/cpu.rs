@@ -158,6 +158,14 @@ impl CPU {
         cpu.PC = cpu.contents(0xFFFC) as u16 + ((cpu.contents(0xFFFD) as u16)<< 8);
         cpu
     }
+    //the reset line: like an interrupt that pushes nothing
+    pub fn reset(&mut self) {
+        self.SP = self.SP.wrapping_sub(3);
+        self.P |= mask(Flags::Interrupt);
+        self.interrupt = Interrupt::Null;
+        self.PC = self.contents(0xFFFC) as u16 + ((self.contents(0xFFFD) as u16)<< 8);
+        self.cycles_delay = 7;
+    }
     pub fn frame(&mut self) {
         self.elapsed = 0;
     }
@@ -351,6 +359,8 @@ impl CPU {
         self.stack_push(pc as u8);
         let p_push = self.P | if isbrk { mask(Flags::Break) } else { 0 } | mask(Flags::BadBit);
         self.stack_push(p_push);
+        //the handler starts with IRQs masked, or a held IRQ line would take it again at once
+        self.P |= mask(Flags::Interrupt);
         self.PC = self.contents(location) as u16 + ((self.contents(location + 1) as u16) << 8);
         self.cycles_delay = 7;
         self.interrupt = Interrupt::Null;
@@ -0,0 +1,214 @@
+use cpu::CPU;
+use cpu::Interrupt;
+use ppu::PPU;
+use ppu::PPUStatus;
+use ines::INES;
+use ines::RomError;
+use mappers::get_mapper;
+use mappers::Mapper;
+use mappers::ChipWrite;
+use apu::*;
+use bincode::{serialize,deserialize};
+
+use std::rc::Rc;
+use std::cell::Ref;
+use std::cell::RefCell;
+use std::fmt;
+use std::error::Error;
+
+//keep at most a second of audio around if nobody is draining it
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize;
+//bump whenever any of the serialized layouts change; untagged states from before this start with a length instead
+const STATE_VERSION: u32 = 3;
+
+pub struct Emulator {
+    cpu: Box<CPU>, //boxed so the pointer handed to the apu stays put
+    mapper: Rc<RefCell<Mapper>>,
+    ppu: Rc<RefCell<PPU>>,
+    samples: Vec<i16>,
+    expansion_level: i32,
+    crc32: u32 //of the rom, to keep states from loading into a different game
+}
+
+#[derive(Serialize,Deserialize)]
+struct NES_State {
+    version: u32,
+    crc32: u32,
+    cpu: Vec<u8>,
+    apu: Vec<u8>,
+    ppu: Vec<u8>,
+    mapper: Vec<u8>
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    Corrupt,
+    WrongVersion,
+    WrongRom
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::Corrupt => write!(f,"savestate is corrupt"),
+            StateError::WrongVersion => write!(f,"savestate was made by a different version of rsnes"),
+            StateError::WrongRom => write!(f,"savestate is from a different rom")
+        }
+    }
+}
+
+impl Error for StateError {}
+
+extern fn apu_contents(cpu:*mut CPU,c: u32) -> i32 {
+    unsafe {
+        (*cpu).contents(c as u16) as i32
+    }
+}
+
+impl Emulator {
+    //the blargg apu is a single global, so only one Emulator should be running at a time
+    pub fn new(ines: INES) -> Result<Emulator,RomError> {
+        let crc32 = ines.crc32;
+        let mapper = get_mapper(ines)?;
+        let ppu = Rc::new(RefCell::new(PPU::new(mapper.clone())));
+        let mut cpu = Box::new(CPU::new(mapper.clone(),ppu.clone()));
+        apu_init(apu_contents,&mut *cpu as *mut CPU);
+        apu_reset();
+        Ok(Emulator {
+            cpu: cpu,
+            mapper: mapper,
+            ppu: ppu,
+            samples: vec![],
+            expansion_level: 0,
+            crc32: crc32
+        })
+    }
+    //runs until the PPU enters vblank
+    pub fn step_frame(&mut self) {
+        let mut done = false;
+        while !done {
+            self.cpu.cycle();
+            self.mapper_cycle();
+            for _ in 0..3 {
+                done |= self.ppu_cycle();
+            }
+        }
+    }
+    //the reset button
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        apu_reset();
+        self.expansion_level = 0;
+    }
+    //NSF only: move delta tracks and restart, giving the 1 based track and track count
+    pub fn change_track(&mut self,delta: i32) -> Option<(usize,usize)> {
+        let track = self.mapper.borrow_mut().change_track(delta);
+        if track.is_some() && delta != 0 {
+            self.reset();
+        }
+        track
+    }
+    //FDS only: eject the disk and insert the next side
+    pub fn switch_disk_side(&mut self) {
+        self.mapper.borrow_mut().switch_disk_side();
+    }
+    //256x240 RGB24
+    pub fn framebuffer<'a>(&'a self) -> Ref<'a,[u8]> {
+        Ref::map(self.ppu.borrow(),|ppu| &ppu.screen[..])
+    }
+    //buttons in the order A, B, Select, Start, Up, Down, Left, Right
+    pub fn set_controller(&mut self,player: usize,buttons: [bool;8]) {
+        match player {
+            0 => self.cpu.inputs = buttons,
+            1 => self.cpu.inputs2 = buttons,
+            _ => ()
+        }
+    }
+    //mono samples at apu::SAMPLE_RATE generated since the last call
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        let mut samples = vec![];
+        std::mem::swap(&mut samples,&mut self.samples);
+        samples
+    }
+    pub fn savedata(&self) -> Vec<u8> {
+        self.mapper.borrow().get_savedata().to_vec()
+    }
+    pub fn save_state(&self) -> Vec<u8> {
+        let serial = NES_State {
+            version: STATE_VERSION,
+            crc32: self.crc32,
+            cpu: self.cpu.serialize(),
+            apu: apu_take_snapshot(),
+            ppu: self.ppu.borrow().serialize(),
+            mapper: self.mapper.borrow().serialize()
+        };
+        serialize(&serial).unwrap()
+    }
+    //an empty state is an unused slot and loads as nothing
+    pub fn load_state(&mut self,data: &[u8]) -> Result<(),StateError> {
+        if data.len() != 0 {
+            //the version comes first in every tagged layout, so check it before trusting the rest
+            let version: u32 = deserialize(data).map_err(|_| StateError::Corrupt)?;
+            if version != STATE_VERSION {
+                return Err(StateError::WrongVersion);
+            }
+            let serial: NES_State = deserialize(data).map_err(|_| StateError::Corrupt)?;
+            if serial.crc32 != self.crc32 {
+                return Err(StateError::WrongRom);
+            }
+            self.cpu.deserialize(&serial.cpu);
+            apu_get_snapshot(&serial.apu);
+            self.ppu.borrow_mut().deserialize(&serial.ppu);
+            self.mapper.borrow_mut().deserialize(&serial.mapper);
+        }
+        Ok(())
+    }
+    fn mapper_cycle(&mut self) {
+        let mut mapper = self.mapper.borrow_mut();
+        mapper.cpu_cycle();
+        if mapper.interrupt() {
+            //a pending NMI wins; a held IRQ line gets another chance next cycle
+            if let Interrupt::Null = self.cpu.interrupt {
+                self.cpu.interrupt = Interrupt::IRQ;
+            }
+        }
+        for write in mapper.chip_writes() {
+            match write {
+                ChipWrite::Vrc6 {osc,reg,what} => apu_vrc6_write(self.cpu.elapsed,osc,reg,what),
+                ChipWrite::Namco {address,what} => apu_namco_write(self.cpu.elapsed,address,what)
+            }
+        }
+        let level = mapper.audio_output();
+        if level != self.expansion_level {
+            apu_expansion(self.cpu.elapsed,level - self.expansion_level);
+            self.expansion_level = level;
+        }
+    }
+    fn ppu_cycle(&mut self) -> bool {
+        let status = self.ppu.borrow_mut().cycle();
+        if status == PPUStatus::VBlank {
+            apu_run_frame(self.cpu.elapsed);
+            self.cpu.frame();
+            if self.ppu.borrow().generate_nmi {
+                self.cpu.interrupt = Interrupt::NMI;
+            }
+            self.read_samples();
+            return true;
+        }
+        false
+    }
+    fn read_samples(&mut self) {
+        let mut buf = [0;4096];
+        loop {
+            let count = apu_read_samples(&mut buf);
+            if count == 0 {
+                break;
+            }
+            self.samples.extend_from_slice(&buf[..count]);
+        }
+        if self.samples.len() > MAX_SAMPLES {
+            let excess = self.samples.len() - MAX_SAMPLES;
+            self.samples.drain(..excess);
+        }
+    }
+}
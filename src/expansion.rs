@@ -0,0 +1,860 @@
+/*
+Expansion audio chips found on cartridges and the disk system.
+Each one is clocked once per CPU cycle by its board and reports a level in
+apu_expansion units (1/256 of a square channel volume step).
+*/
+
+//volume and modulation envelopes share a layout
+#[derive(Serialize,Deserialize,Clone)]
+struct FdsEnvelope {
+    speed: u8,
+    gain: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+    frequency: u16
+}
+
+impl FdsEnvelope {
+    fn new() -> FdsEnvelope {
+        FdsEnvelope {speed: 0,gain: 0,increase: false,disabled: true,timer: 0,frequency: 0}
+    }
+    fn reset_timer(&mut self,master_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+    fn write(&mut self,register: u16,what: u8,master_speed: u8) {
+        match register & 3 {
+            0 => {
+                self.speed = what & 0x3F;
+                self.increase = what & 0x40 != 0;
+                self.disabled = what & 0x80 != 0;
+                self.reset_timer(master_speed);
+                if self.disabled {
+                    self.gain = self.speed;
+                }
+            }
+            2 => self.frequency = self.frequency & 0xF00 | what as u16,
+            3 => self.frequency = self.frequency & 0xFF | ((what as u16 & 0xF) << 8),
+            _ => ()
+        }
+    }
+    //true when the gain changed
+    fn tick(&mut self,master_speed: u8) -> bool {
+        if self.disabled || master_speed == 0 {
+            return false;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.reset_timer(master_speed);
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+            return true;
+        }
+        false
+    }
+}
+
+//FDS wavetable channel with its frequency modulator
+//https://wiki.nesdev.com/w/index.php/FDS_audio
+#[derive(Serialize,Deserialize,Clone)]
+pub struct FdsAudio {
+    wave: Vec<u8>, //64 6-bit samples
+    wave_position: usize,
+    wave_accumulator: u16,
+    wave_write: bool,
+    halt_wave: bool,
+    halt_envelopes: bool,
+    master_volume: u8,
+    master_speed: u8,
+    volume: FdsEnvelope,
+    modulator: FdsEnvelope,
+    mod_table: Vec<u8>, //64 3-bit steps
+    mod_position: usize,
+    mod_accumulator: u16,
+    mod_counter: i32,
+    mod_halt: bool,
+    mod_output: i32,
+    level: i32
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave: vec![0;64],
+            wave_position: 0,
+            wave_accumulator: 0,
+            wave_write: false,
+            halt_wave: true,
+            halt_envelopes: false,
+            master_volume: 0,
+            master_speed: 0xE8,
+            volume: FdsEnvelope::new(),
+            modulator: FdsEnvelope::new(),
+            mod_table: vec![0;64],
+            mod_position: 0,
+            mod_accumulator: 0,
+            mod_counter: 0,
+            mod_halt: true,
+            mod_output: 0,
+            level: 0
+        }
+    }
+    pub fn read(&mut self,location: u16) -> u8 {
+        match location {
+            0x4040...0x407F => self.wave[location as usize - 0x4040] | 0x40,
+            0x4090 => self.volume.gain | 0x40,
+            0x4092 => self.modulator.gain | 0x40,
+            _ => 0
+        }
+    }
+    pub fn write(&mut self,location: u16,what: u8) {
+        match location {
+            0x4040...0x407F => if self.wave_write {
+                self.wave[location as usize - 0x4040] = what & 0x3F;
+            },
+            0x4080 | 0x4082 => self.volume.write(location,what,self.master_speed),
+            0x4083 => {
+                self.volume.write(location,what,self.master_speed);
+                self.halt_envelopes = what & 0x40 != 0;
+                self.halt_wave = what & 0x80 != 0;
+                if self.halt_envelopes {
+                    self.volume.reset_timer(self.master_speed);
+                    self.modulator.reset_timer(self.master_speed);
+                }
+            }
+            0x4084 | 0x4086 => self.modulator.write(location,what,self.master_speed),
+            0x4085 => self.set_mod_counter((what & 0x7F) as i32),
+            0x4087 => {
+                self.modulator.write(location,what,self.master_speed);
+                self.mod_halt = what & 0x80 != 0;
+                if self.mod_halt {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 => if self.mod_halt {
+                self.mod_table[self.mod_position] = what & 7;
+                self.mod_table[(self.mod_position + 1) & 0x3F] = what & 7;
+                self.mod_position = (self.mod_position + 2) & 0x3F;
+            },
+            0x4089 => {
+                self.master_volume = what & 3;
+                self.wave_write = what & 0x80 != 0;
+            }
+            0x408A => self.master_speed = what,
+            _ => ()
+        }
+    }
+    pub fn cycle(&mut self) {
+        let frequency = self.volume.frequency as i32;
+        if !self.halt_wave && !self.halt_envelopes {
+            self.volume.tick(self.master_speed);
+            if self.modulator.tick(self.master_speed) {
+                self.update_mod_output(frequency);
+            }
+        }
+        if self.tick_modulator() {
+            self.update_mod_output(frequency);
+        }
+        if self.halt_wave {
+            self.wave_position = 0;
+        } else {
+            let pitch = frequency + self.mod_output;
+            if pitch > 0 && !self.wave_write {
+                let (sum,overflow) = self.wave_accumulator.overflowing_add(pitch as u16);
+                self.wave_accumulator = sum;
+                if overflow {
+                    self.wave_position = (self.wave_position + 1) & 0x3F;
+                }
+            }
+        }
+        //the wave is held while it is being written
+        if !self.wave_write {
+            let gain = self.volume.gain.min(32) as i32;
+            let level = self.wave[self.wave_position] as i32 * gain * [36,24,17,14][self.master_volume as usize] / 1152;
+            //0-63, with 63 about 2.4 times a full volume square channel
+            self.level = level * 146;
+        }
+    }
+    pub fn output(&self) -> i32 {
+        self.level
+    }
+    fn set_mod_counter(&mut self,value: i32) {
+        self.mod_counter = if value >= 64 { value - 128 } else if value < -64 { value + 128 } else { value };
+    }
+    fn tick_modulator(&mut self) -> bool {
+        let frequency = self.modulator.frequency;
+        if self.mod_halt || frequency == 0 {
+            return false;
+        }
+        let (sum,overflow) = self.mod_accumulator.overflowing_add(frequency);
+        self.mod_accumulator = sum;
+        if !overflow {
+            return false;
+        }
+        let step = self.mod_table[self.mod_position];
+        let counter = match step {
+            4 => 0,
+            _ => self.mod_counter + [0,1,2,4,0,-4,-2,-1][step as usize]
+        };
+        self.set_mod_counter(counter);
+        self.mod_position = (self.mod_position + 1) & 0x3F;
+        true
+    }
+    //pitch offset from the nesdev wiki's description of the modulator
+    fn update_mod_output(&mut self,frequency: i32) {
+        let mut temp = self.mod_counter * self.modulator.gain as i32;
+        let remainder = temp & 0xF;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            if self.mod_counter < 0 {
+                temp -= 1;
+            } else {
+                temp += 2;
+            }
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= frequency;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.mod_output = temp;
+    }
+}
+
+//length counter loads, shared with the 2A03
+const LENGTH_TABLE: [u8;32] = [
+    10,254,20,2,40,4,80,6,160,8,60,10,14,12,26,14,
+    12,16,24,18,48,20,96,22,192,24,72,26,16,28,32,30];
+
+const DUTY_TABLE: [[u8;8];4] = [
+    [0,1,0,0,0,0,0,0],
+    [0,1,1,0,0,0,0,0],
+    [0,1,1,1,1,0,0,0],
+    [1,0,0,1,1,1,1,1]];
+
+//a 2A03 pulse channel without the sweep unit
+#[derive(Serialize,Deserialize,Clone)]
+struct Mmc5Pulse {
+    duty: u8,
+    halt: bool, //also loops the envelope
+    constant: bool,
+    volume: u8,
+    period: u16,
+    timer: u16,
+    step: usize,
+    length: u8,
+    enabled: bool,
+    envelope_start: bool,
+    envelope_divider: u8,
+    envelope_decay: u8
+}
+
+impl Mmc5Pulse {
+    fn new() -> Mmc5Pulse {
+        Mmc5Pulse {
+            duty: 0,
+            halt: false,
+            constant: false,
+            volume: 0,
+            period: 0,
+            timer: 0,
+            step: 0,
+            length: 0,
+            enabled: false,
+            envelope_start: false,
+            envelope_divider: 0,
+            envelope_decay: 0
+        }
+    }
+    fn write(&mut self,register: u16,what: u8) {
+        match register & 3 {
+            0 => {
+                self.duty = what >> 6;
+                self.halt = what & 0x20 != 0;
+                self.constant = what & 0x10 != 0;
+                self.volume = what & 0xF;
+            }
+            2 => self.period = self.period & 0x700 | what as u16,
+            3 => {
+                self.period = self.period & 0xFF | ((what as u16 & 7) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[what as usize >> 3];
+                }
+                self.step = 0;
+                self.envelope_start = true;
+            }
+            _ => ()
+        }
+    }
+    fn set_enabled(&mut self,enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+    //every other CPU cycle
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+    //envelope and length counter together, about 240 times a second
+    fn clock_quarter_frame(&mut self) {
+        if self.envelope_start {
+            self.envelope_start = false;
+            self.envelope_decay = 15;
+            self.envelope_divider = self.volume;
+        } else if self.envelope_divider == 0 {
+            self.envelope_divider = self.volume;
+            if self.envelope_decay > 0 {
+                self.envelope_decay -= 1;
+            } else if self.halt {
+                self.envelope_decay = 15;
+            }
+        } else {
+            self.envelope_divider -= 1;
+        }
+        if self.length > 0 && !self.halt {
+            self.length -= 1;
+        }
+    }
+    //unlike the 2A03's, low periods aren't muted
+    fn output(&self) -> i32 {
+        if self.length == 0 || DUTY_TABLE[self.duty as usize][self.step] == 0 {
+            0
+        } else if self.constant {
+            self.volume as i32
+        } else {
+            self.envelope_decay as i32
+        }
+    }
+}
+
+//MMC5 sound: two pulse channels and an 8-bit PCM channel
+//https://wiki.nesdev.com/w/index.php/MMC5_audio
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Mmc5Audio {
+    pulses: [Mmc5Pulse;2],
+    odd_cycle: bool,
+    frame_counter: u32,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    pcm: u8
+}
+
+//the MMC5 doesn't have the 2A03's frame counter, just a fixed ~240 Hz divider
+const MMC5_QUARTER_FRAME: u32 = 7457;
+
+impl Mmc5Audio {
+    pub fn new() -> Mmc5Audio {
+        Mmc5Audio {
+            pulses: [Mmc5Pulse::new(),Mmc5Pulse::new()],
+            odd_cycle: false,
+            frame_counter: 0,
+            pcm_read_mode: false,
+            pcm_irq_enabled: false,
+            pcm_irq: false,
+            pcm: 0
+        }
+    }
+    pub fn read(&mut self,location: u16) -> u8 {
+        match location {
+            0x5010 => {
+                let result = ((self.pcm_irq && self.pcm_irq_enabled) as u8) << 7 | self.pcm_read_mode as u8;
+                self.pcm_irq = false;
+                result
+            }
+            0x5015 => (self.pulses[0].length > 0) as u8 | ((self.pulses[1].length > 0) as u8) << 1,
+            _ => 0
+        }
+    }
+    pub fn write(&mut self,location: u16,what: u8) {
+        match location {
+            0x5000...0x5003 => self.pulses[0].write(location,what),
+            0x5004...0x5007 => self.pulses[1].write(location,what),
+            0x5010 => {
+                self.pcm_read_mode = what & 1 != 0;
+                self.pcm_irq_enabled = what & 0x80 != 0;
+            }
+            0x5011 => if !self.pcm_read_mode && what != 0 {
+                self.pcm = what;
+            },
+            0x5015 => {
+                self.pulses[0].set_enabled(what & 1 != 0);
+                self.pulses[1].set_enabled(what & 2 != 0);
+            }
+            _ => ()
+        }
+    }
+    //in read mode the channel plays whatever the CPU reads from $8000-$BFFF; a zero raises the IRQ
+    pub fn pcm_read(&mut self,what: u8) {
+        if self.pcm_read_mode {
+            if what == 0 {
+                self.pcm_irq = true;
+            } else {
+                self.pcm = what;
+            }
+        }
+    }
+    pub fn interrupt(&self) -> bool {
+        self.pcm_irq && self.pcm_irq_enabled
+    }
+    pub fn cycle(&mut self) {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_timer();
+            }
+        }
+        self.frame_counter += 1;
+        if self.frame_counter == MMC5_QUARTER_FRAME {
+            self.frame_counter = 0;
+            for pulse in self.pulses.iter_mut() {
+                pulse.clock_quarter_frame();
+            }
+        }
+    }
+    //the PCM channel at full scale is about as loud as a pulse at full volume
+    pub fn output(&self) -> i32 {
+        (self.pulses[0].output() + self.pulses[1].output()) * 256 + self.pcm as i32 * 16
+    }
+}
+
+//the 5B's 32 step logarithmic volume curve, 1.5 dB a step; a channel at full volume is
+//about as loud as a square channel at full volume
+const SUNSOFT5B_VOLUME: [i32;32] = [
+    0,22,26,31,36,43,51,61,72,86,102,121,144,172,204,242,
+    288,342,407,483,575,683,812,965,1146,1362,1619,1925,2287,2719,3231,3840];
+
+//Sunsoft 5B: a YM2149 with three square channels, noise and an envelope
+//https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Sunsoft5bAudio {
+    address: u8,
+    registers: [u8;16],
+    divider: u8,
+    tone_counters: [u16;3],
+    tone_outputs: [bool;3],
+    noise_counter: u16,
+    noise: u32, //17 bit LFSR
+    envelope_counter: u32,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool
+}
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Sunsoft5bAudio {
+        Sunsoft5bAudio {
+            address: 0,
+            registers: [0;16],
+            divider: 0,
+            tone_counters: [0;3],
+            tone_outputs: [false;3],
+            noise_counter: 0,
+            noise: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: true
+        }
+    }
+    //$C000 selects a register, $E000 writes it
+    pub fn write(&mut self,location: u16,what: u8) {
+        match location {
+            0xC000...0xDFFF => self.address = what & 0xF,
+            0xE000...0xFFFF => {
+                self.registers[self.address as usize] = what;
+                if self.address == 13 {
+                    self.envelope_step = 0;
+                    self.envelope_counter = 0;
+                    self.envelope_attack = what & 4 != 0;
+                    self.envelope_holding = false;
+                }
+            }
+            _ => ()
+        }
+    }
+    fn tone_period(&self,channel: usize) -> u16 {
+        (self.registers[channel*2] as u16 | (self.registers[channel*2 + 1] as u16 & 0xF) << 8).max(1)
+    }
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        //the end of a ramp: shapes without continue drop to 0, otherwise hold, alternate or repeat
+        let shape = self.registers[13];
+        if shape & 8 == 0 {
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+        } else if shape & 1 != 0 {
+            self.envelope_holding = true;
+            if shape & 2 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+        } else {
+            if shape & 2 != 0 {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+    fn envelope_level(&self) -> usize {
+        if self.envelope_attack {
+            self.envelope_step as usize
+        } else {
+            31 - self.envelope_step as usize
+        }
+    }
+    //the tone, noise and envelope generators all run off CPU clock / 16
+    pub fn cycle(&mut self) {
+        self.divider = (self.divider + 1) & 0xF;
+        if self.divider != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+        self.noise_counter += 1;
+        if self.noise_counter >= (self.registers[6] as u16 & 0x1F).max(1) * 2 {
+            self.noise_counter = 0;
+            let feedback = (self.noise ^ self.noise >> 3) & 1;
+            self.noise = self.noise >> 1 | feedback << 16;
+        }
+        let envelope_period = (self.registers[11] as u32 | (self.registers[12] as u32) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter >= envelope_period {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+    pub fn output(&self) -> i32 {
+        let mixer = self.registers[7];
+        let mut result = 0;
+        for channel in 0..3 {
+            let tone = self.tone_outputs[channel] || mixer & (1 << channel) != 0;
+            let noise = self.noise & 1 != 0 || mixer & (8 << channel) != 0;
+            if tone && noise {
+                let volume = self.registers[8 + channel];
+                let level = if volume & 0x10 != 0 {
+                    self.envelope_level()
+                } else if volume & 0xF == 0 {
+                    0
+                } else {
+                    (volume as usize & 0xF) * 2 + 1
+                };
+                result += SUNSOFT5B_VOLUME[level];
+            }
+        }
+        result
+    }
+}
+
+//the fifteen instruments built into the VRC7; instrument 0 is the custom patch at registers 0-7
+const VRC7_PATCHES: [[u8;8];15] = [
+    [0x03,0x21,0x05,0x06,0xE8,0x81,0x42,0x27],
+    [0x13,0x41,0x14,0x0D,0xD8,0xF6,0x23,0x12],
+    [0x11,0x11,0x08,0x08,0xFA,0xB2,0x20,0x12],
+    [0x31,0x61,0x0C,0x07,0xA8,0x64,0x61,0x27],
+    [0x32,0x21,0x1E,0x06,0xE1,0x76,0x01,0x28],
+    [0x02,0x01,0x06,0x00,0xA3,0xE2,0xF4,0xF4],
+    [0x21,0x61,0x1D,0x07,0x82,0x81,0x11,0x07],
+    [0x23,0x21,0x22,0x17,0xA2,0x72,0x01,0x17],
+    [0x35,0x11,0x25,0x00,0x40,0x73,0x72,0x01],
+    [0xB5,0x01,0x0F,0x0F,0xA8,0xA5,0x51,0x02],
+    [0x17,0xC1,0x24,0x07,0xF8,0xF8,0x22,0x12],
+    [0x71,0x23,0x11,0x06,0x65,0x74,0x18,0x16],
+    [0x01,0x02,0xD3,0x05,0xC9,0x95,0x03,0x02],
+    [0x61,0x63,0x0C,0x00,0x94,0xC0,0x33,0xF6],
+    [0x21,0x72,0x0D,0x00,0xC1,0xD5,0x56,0x06]];
+
+//frequency multiplier, doubled so 1/2 fits
+const VRC7_MULTIPLIERS: [u32;16] = [1,2,4,6,8,10,12,14,16,18,20,20,24,24,30,30];
+
+//key scale level at 6 dB/octave for the top 4 bits of the frequency, in envelope steps
+const VRC7_KEY_SCALE: [i32;16] = [0,48,64,74,80,86,90,94,96,100,102,104,106,108,110,112];
+
+//the FM core runs at the CPU clock / 36, about 49.7 kHz
+const VRC7_CLOCK_DIVIDER: u8 = 36;
+const VRC7_SAMPLE_RATE: f64 = 1789773.0 / 36.0;
+
+//envelope attenuation counts 0.375 dB steps in 16.16 fixed point; 127 steps is silent
+const VRC7_ENVELOPE_STEP: f64 = 0.375;
+const VRC7_ENVELOPE_MAX: u32 = 127 << 16;
+
+//one channel at full volume swings this far either way
+const VRC7_CHANNEL_LEVEL: f64 = 2048.0;
+
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+enum Vrc7EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release
+}
+
+//operator 0 of a channel is the modulator, operator 1 the carrier
+#[derive(Serialize,Deserialize,Clone)]
+struct Vrc7Operator {
+    phase: u32, //20 bits a cycle
+    envelope: u32,
+    state: Vrc7EnvelopeState,
+    output: [f64;2] //the last two outputs, for modulator feedback
+}
+
+impl Vrc7Operator {
+    fn new() -> Vrc7Operator {
+        Vrc7Operator {phase: 0,envelope: VRC7_ENVELOPE_MAX,state: Vrc7EnvelopeState::Release,output: [0.0;2]}
+    }
+    //the envelope moves (4 + rate low bits) << (rate / 4) 16.16 steps a sample
+    fn rate_increment(rate: u32,key_scale: u32) -> u32 {
+        if rate == 0 {
+            return 0;
+        }
+        let rate = (rate*4 + key_scale).min(63);
+        (4 + (rate & 3)) << (rate >> 2)
+    }
+    fn clock_envelope(&mut self,patch: &[u8;8],operator: usize,key_scale: u32,sustain: bool) {
+        let attack = (patch[4 + operator] >> 4) as u32;
+        let decay = (patch[4 + operator] & 0xF) as u32;
+        let sustain_level = ((patch[6 + operator] >> 4) as u32 * 8) << 16;
+        let release = (patch[6 + operator] & 0xF) as u32;
+        let sustained = patch[operator] & 0x20 != 0;
+        match self.state {
+            Vrc7EnvelopeState::Attack => {
+                //attack is exponential, fast from silence and slowing near full volume
+                if attack == 15 {
+                    self.envelope = 0;
+                } else {
+                    let increment = Vrc7Operator::rate_increment(attack,key_scale) as u64;
+                    let step = ((self.envelope as u64 * increment) >> 18) as u32 + (increment > 0) as u32;
+                    self.envelope = self.envelope.saturating_sub(step);
+                }
+                if self.envelope == 0 {
+                    self.state = Vrc7EnvelopeState::Decay;
+                }
+            }
+            Vrc7EnvelopeState::Decay => {
+                self.envelope += Vrc7Operator::rate_increment(decay,key_scale);
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.state = Vrc7EnvelopeState::Sustain;
+                }
+            }
+            //sustained patches hold until key off; the others fade at the release rate
+            Vrc7EnvelopeState::Sustain => if !sustained {
+                self.envelope += Vrc7Operator::rate_increment(release,key_scale);
+            },
+            Vrc7EnvelopeState::Release => {
+                let rate = if sustain { 5 } else if sustained { release } else { 7 };
+                self.envelope += Vrc7Operator::rate_increment(rate,key_scale);
+            }
+        }
+        self.envelope = self.envelope.min(VRC7_ENVELOPE_MAX);
+    }
+    //a sine wave, with the negative half dropped for rectified patches
+    fn wave(&self,modulation: f64,rectified: bool,attenuation: f64) -> f64 {
+        if self.envelope >= VRC7_ENVELOPE_MAX {
+            return 0.0;
+        }
+        let cycle = self.phase as f64 / (1 << 20) as f64 + modulation;
+        let wave = (cycle * 2.0 * ::std::f64::consts::PI).sin();
+        if rectified && wave < 0.0 {
+            0.0
+        } else {
+            let decibels = (self.envelope as f64 / 65536.0 + attenuation) * VRC7_ENVELOPE_STEP;
+            wave * 10f64.powf(-decibels / 20.0)
+        }
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone)]
+struct Vrc7Channel {
+    frequency: u16, //9 bits
+    block: u8,
+    key: bool,
+    sustain: bool,
+    instrument: u8,
+    volume: u8,
+    operators: [Vrc7Operator;2]
+}
+
+impl Vrc7Channel {
+    fn new() -> Vrc7Channel {
+        Vrc7Channel {
+            frequency: 0,
+            block: 0,
+            key: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+            operators: [Vrc7Operator::new(),Vrc7Operator::new()]
+        }
+    }
+}
+
+//Konami VRC7: six two-operator FM channels, a cut down YM2413 (OPLL)
+//https://wiki.nesdev.com/w/index.php/VRC7_audio
+#[derive(Serialize,Deserialize,Clone)]
+pub struct Vrc7Audio {
+    address: u8,
+    custom: [u8;8],
+    channels: [Vrc7Channel;6],
+    divider: u8,
+    tremolo: u32, //LFO positions in samples
+    vibrato: u32,
+    output: i32
+}
+
+impl Vrc7Audio {
+    pub fn new() -> Vrc7Audio {
+        Vrc7Audio {
+            address: 0,
+            custom: [0;8],
+            channels: [Vrc7Channel::new(),Vrc7Channel::new(),Vrc7Channel::new(),
+                       Vrc7Channel::new(),Vrc7Channel::new(),Vrc7Channel::new()],
+            divider: 0,
+            tremolo: 0,
+            vibrato: 0,
+            output: 0
+        }
+    }
+    //$9010 selects a register, $9030 writes it
+    pub fn write(&mut self,location: u16,what: u8) {
+        match location {
+            0x9010 => self.address = what,
+            0x9030 => self.write_register(what),
+            _ => ()
+        }
+    }
+    fn write_register(&mut self,what: u8) {
+        let index = (self.address & 0xF) as usize;
+        match self.address {
+            0x00...0x07 => self.custom[index] = what,
+            0x10...0x15 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = channel.frequency & 0x100 | what as u16;
+            }
+            0x20...0x25 => {
+                let channel = &mut self.channels[index];
+                channel.frequency = channel.frequency & 0xFF | (what as u16 & 1) << 8;
+                channel.block = (what >> 1) & 7;
+                channel.sustain = what & 0x20 != 0;
+                let key = what & 0x10 != 0;
+                if key && !channel.key {
+                    for operator in channel.operators.iter_mut() {
+                        operator.phase = 0;
+                        operator.state = Vrc7EnvelopeState::Attack;
+                    }
+                } else if !key && channel.key {
+                    //only the carrier is released
+                    channel.operators[1].state = Vrc7EnvelopeState::Release;
+                }
+                channel.key = key;
+            }
+            0x30...0x35 => {
+                let channel = &mut self.channels[index];
+                channel.instrument = what >> 4;
+                channel.volume = what & 0xF;
+            }
+            _ => ()
+        }
+    }
+    fn patch(&self,instrument: u8) -> [u8;8] {
+        if instrument == 0 {
+            self.custom
+        } else {
+            VRC7_PATCHES[instrument as usize - 1]
+        }
+    }
+    //one sample from all six channels
+    fn sample(&mut self) -> f64 {
+        use std::f64::consts::PI;
+        //tremolo is 4.8 dB at 3.7 Hz, vibrato 14 cents at 6.4 Hz
+        self.tremolo = (self.tremolo + 1) % (VRC7_SAMPLE_RATE / 3.7) as u32;
+        self.vibrato = (self.vibrato + 1) % (VRC7_SAMPLE_RATE / 6.4) as u32;
+        let tremolo_phase = self.tremolo as f64 * 3.7 / VRC7_SAMPLE_RATE;
+        let tremolo = (1.0 - (tremolo_phase * 2.0 * PI).cos()) / 2.0 * 4.8 / VRC7_ENVELOPE_STEP;
+        let vibrato_phase = self.vibrato as f64 * 6.4 / VRC7_SAMPLE_RATE;
+        let vibrato = 1.0 + (vibrato_phase * 2.0 * PI).sin() * 0.0081;
+        let mut result = 0.0;
+        for index in 0..6 {
+            let patch = self.patch(self.channels[index].instrument);
+            let channel = &mut self.channels[index];
+            let rate_scale = (channel.block as u32) << 1 | (channel.frequency >> 8) as u32;
+            let level_scale = (VRC7_KEY_SCALE[(channel.frequency >> 5) as usize] - 16*(7 - channel.block as i32)).max(0);
+            let mut modulation = 0.0;
+            for operator in 0..2 {
+                let flags = patch[operator];
+                let key_scale = if flags & 0x10 != 0 { rate_scale } else { rate_scale >> 2 };
+                let ksl = patch[2 + operator] >> 6;
+                let mut attenuation = if ksl == 0 { 0 } else { level_scale >> (3 - ksl) } as f64;
+                attenuation += if operator == 0 {
+                    (patch[2] & 0x3F) as f64 * 2.0
+                } else {
+                    channel.volume as f64 * 8.0
+                };
+                if flags & 0x80 != 0 {
+                    attenuation += tremolo;
+                }
+                let mut increment = ((channel.frequency as u32) << channel.block) * VRC7_MULTIPLIERS[(flags & 0xF) as usize];
+                if flags & 0x40 != 0 {
+                    increment = (increment as f64 * vibrato) as u32;
+                }
+                let slot = &mut channel.operators[operator];
+                slot.clock_envelope(&patch,operator,key_scale,channel.sustain);
+                slot.phase = (slot.phase + increment) & 0xFFFFF;
+                let rectified = patch[3] & (8 << operator) != 0;
+                if operator == 0 {
+                    //feedback phase modulates the modulator by up to half a cycle
+                    let amount = patch[3] & 7;
+                    let feedback = if amount == 0 {
+                        0.0
+                    } else {
+                        (slot.output[0] + slot.output[1]) / 2.0 * (2.0f64).powi(amount as i32 - 8)
+                    };
+                    let output = slot.wave(feedback,rectified,attenuation);
+                    slot.output = [slot.output[1],output];
+                    //and shifts the carrier's phase by up to two cycles
+                    modulation = output * 2.0;
+                } else {
+                    result += slot.wave(modulation,rectified,attenuation);
+                }
+            }
+        }
+        result
+    }
+    pub fn cycle(&mut self) {
+        self.divider += 1;
+        if self.divider == VRC7_CLOCK_DIVIDER {
+            self.divider = 0;
+            self.output = (self.sample() * VRC7_CHANNEL_LEVEL) as i32;
+        }
+    }
+    pub fn output(&self) -> i32 {
+        self.output
+    }
+}
@@ -0,0 +1,115 @@
+use ines::INES;
+use ines::RomError;
+
+/*
+.fds images are disk sides of 65500 bytes, optionally after a 16 byte fwNES
+header ("FDS\x1A", side count). The blocks on a side are stored without the
+gaps, start marks and CRCs the drive sees, so those are put back for the
+drive and taken out again before disk writes are saved.
+https://wiki.nesdev.com/w/index.php/FDS_file_format
+*/
+
+pub const SIDE_SIZE: usize = 65500;
+pub const BIOS_SIZE: usize = 0x2000;
+//a side as the drive sees it; room for the gaps plus the whole side
+const RAW_SIDE_SIZE: usize = 80000;
+
+pub fn is_fds(data: &[u8]) -> bool {
+    data.starts_with(b"FDS\x1A") || data.starts_with(b"\x01*NINTENDO-HVC*")
+}
+
+//the disk system is treated as mapper 20: the BIOS is the PRG-ROM, 32 KiB of PRG-RAM,
+//8 KiB of CHR-RAM, and the disk sides are the battery backed save data
+pub fn parse(data: &[u8],bios: Vec<u8>) -> Result<INES,RomError> {
+    if !is_fds(data) {
+        return Err(RomError::BadMagic);
+    }
+    if bios.len() != BIOS_SIZE {
+        return Err(RomError::BadBios(bios.len()));
+    }
+    let disk = if data.starts_with(b"FDS\x1A") { &data[16.min(data.len())..] } else { data };
+    let sides = (disk.len() + SIDE_SIZE - 1) / SIDE_SIZE;
+    if sides == 0 {
+        return Err(RomError::TruncatedPrg);
+    }
+    let mut result = INES::empty();
+    result.mapper = 20;
+    result.prgrom = bios;
+    result.prgrom_size = 1;
+    result.chrram_size = 0x2000;
+    result.chrrom = vec![0;0x2000];
+    result.prgram_size = 0x8000;
+    result.persistent_memory = true;
+    result.savedata = vec![0;sides * SIDE_SIZE];
+    result.savedata[..disk.len()].copy_from_slice(disk);
+    Ok(result)
+}
+
+fn block_length(side: &[u8],position: usize,file_size: usize) -> Option<usize> {
+    match side[position] {
+        1 => Some(56), //disk info
+        2 => Some(2), //file count
+        3 => Some(16), //file header
+        4 => Some(1 + file_size), //file data
+        _ => None
+    }
+}
+
+//file headers keep the size of the data block that follows at bytes 13-14
+fn file_size(header: &[u8]) -> usize {
+    header[13] as usize | (header[14] as usize) << 8
+}
+
+//insert the lead-in gap, start marks, CRCs and gaps between blocks
+pub fn raw_side(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0;28300 / 8];
+    let mut position = 0;
+    let mut size = 0;
+    while position < side.len() {
+        let length = match block_length(side,position,size) {
+            Some(length) if position + length <= side.len() => length,
+            _ => break
+        };
+        if side[position] == 3 {
+            size = file_size(&side[position..]);
+        }
+        raw.push(0x80);
+        raw.extend_from_slice(&side[position..position + length]);
+        //the CRC isn't checked when reading, so any value will do
+        raw.extend_from_slice(&[0x4D,0x62]);
+        raw.extend_from_slice(&[0;976 / 8]);
+        position += length;
+    }
+    if raw.len() < RAW_SIDE_SIZE {
+        raw.resize(RAW_SIDE_SIZE,0);
+    }
+    raw
+}
+
+//the inverse of raw_side, padded back out to SIDE_SIZE
+pub fn pack_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = vec![];
+    let mut position = 0;
+    let mut size = 0;
+    while position < raw.len() {
+        if raw[position] != 0x80 {
+            position += 1;
+            continue;
+        }
+        position += 1;
+        if position >= raw.len() {
+            break;
+        }
+        let length = match block_length(raw,position,size) {
+            Some(length) if position + length <= raw.len() => length,
+            _ => break
+        };
+        if raw[position] == 3 {
+            size = file_size(&raw[position..]);
+        }
+        side.extend_from_slice(&raw[position..position + length]);
+        position += length + 2;
+    }
+    side.resize(SIDE_SIZE,0);
+    side
+}
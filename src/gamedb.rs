@@ -0,0 +1,73 @@
+use ines;
+use ines::INES;
+use ines::Timing;
+
+/*
+Known dumps keyed by the CRC32 of PRG+CHR, used to correct iNES 1.0 headers.
+One game a line in gamedb.txt: crc32 mapper mirroring battery region title, where the
+mapper can carry a NES 2.0 submapper as mapper.submapper for boards that need one
+*/
+const DATABASE: &'static str = include_str!("gamedb.txt");
+
+pub struct GameInfo {
+    pub mapper: u16,
+    pub submapper: u8,
+    pub vertical_mirroring: bool,
+    pub four_screen: bool,
+    pub battery: bool,
+    pub timing: Timing,
+    pub title: String
+}
+
+pub fn lookup(crc32: u32) -> Option<GameInfo> {
+    for line in DATABASE.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.splitn(6,' ').collect();
+        if fields.len() != 6 || u32::from_str_radix(fields[0],16) != Ok(crc32) {
+            continue;
+        }
+        let mut mapper = fields[1].splitn(2,'.');
+        return Some(GameInfo {
+            mapper: mapper.next().unwrap().parse().unwrap_or(0),
+            submapper: mapper.next().and_then(|x| x.parse().ok()).unwrap_or(0),
+            vertical_mirroring: fields[2] == "V",
+            four_screen: fields[2] == "4",
+            battery: fields[3] == "1",
+            timing: match fields[4] {
+                "PAL" => Timing::PAL,
+                "Dendy" => Timing::Dendy,
+                _ => Timing::NTSC
+            },
+            title: fields[5].to_string()
+        });
+    }
+    None
+}
+
+//overrides the header with what the database knows; false if the game isn't in it
+pub fn correct(ines: &mut INES) -> bool {
+    let info = match lookup(ines.crc32) {
+        Some(info) => info,
+        None => return false
+    };
+    ines.mapper = info.mapper;
+    ines.submapper = info.submapper;
+    ines.vertical_mirroring = info.vertical_mirroring;
+    ines.four_screen = info.four_screen;
+    ines.timing = info.timing;
+    //the header's mapper picked the PRG-RAM size and board memory, so size them again for this one
+    let ram = ines::ines1_prgram_size(ines.mapper);
+    ines.persistent_memory = info.battery;
+    if info.battery {
+        ines.prgnvram_size = ram;
+        ines.prgram_size = 0;
+    } else {
+        ines.prgram_size = ram;
+        ines.prgnvram_size = 0;
+    }
+    ines.savedata = vec![0;ram + ines::board_nvram_size(ines.mapper)];
+    ines.title = Some(info.title);
+    true
+}
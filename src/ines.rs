@@ -1,65 +1,346 @@
 use std::fs::File;
+use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::vec::Vec;
 use std::path::Path;
+use std::fmt;
+use std::error::Error;
+use unif;
+use fds;
+use nsf;
+use nsf::NsfHeader;
+use patch;
+use archive;
+use gamedb;
+use sha1_smol::Sha1;
+use crc32fast;
+
+#[derive(Debug)]
+pub enum RomError {
+    MissingFile(String),
+    Io(io::Error),
+    BadMagic,
+    TruncatedPrg,
+    TruncatedChr,
+    UnsupportedMapper(u16),
+    UnsupportedBoard(String),
+    MissingBios,
+    BadBios(usize),
+    BadPatch(&'static str),
+    Archive(String),
+    MissingEntry(String)
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self,f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::MissingFile(ref name) => write!(f,"{} does not exist",name),
+            RomError::Io(ref e) => write!(f,"i/o error: {}",e),
+            RomError::BadMagic => write!(f,"not an iNES, UNIF, FDS or NSF file"),
+            RomError::TruncatedPrg => write!(f,"file is too short for the PRG-ROM size in its header"),
+            RomError::TruncatedChr => write!(f,"file is too short for the CHR-ROM size in its header"),
+            RomError::UnsupportedMapper(id) => write!(f,"mapper {} is not supported",id),
+            RomError::UnsupportedBoard(ref name) => write!(f,"board {} is not supported",name),
+            RomError::MissingBios => write!(f,"disk images need the FDS BIOS; put disksys.rom beside the image or pass --bios"),
+            RomError::BadBios(len) => write!(f,"the FDS BIOS should be 8192 bytes, not {}",len),
+            RomError::BadPatch(problem) => write!(f,"couldn't apply patch: {}",problem),
+            RomError::Archive(ref problem) => write!(f,"couldn't read archive: {}",problem),
+            RomError::MissingEntry(ref name) => write!(f,"the archive has no file named {}",name)
+        }
+    }
+}
+
+impl Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> RomError {
+        RomError::Io(e)
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Timing {
+    NTSC,
+    PAL,
+    Multiple,
+    Dendy
+}
+
+//everything besides the rom that goes into loading it
+#[derive(Default,Clone)]
+pub struct LoadOptions {
+    pub savefile: Option<String>,
+    pub bios: Option<String>, //FDS BIOS, otherwise disksys.rom beside the image or in the working directory
+    pub patches: Vec<String>, //IPS/UPS/BPS patches applied in order, otherwise one beside the rom with the same name
+    pub entry: Option<String> //file to load out of a zip, otherwise the first rom in it
+}
+
 pub struct INES {
-    pub prgrom_size: u8,
-    pub chrrom_size: u8,
+    pub prgrom_size: u16, //16 KiB units
+    pub chrrom_size: u16, //8 KiB units
     pub vertical_mirroring: bool,
     pub persistent_memory: bool,
-    pub ignore_mirroring: bool,
-    pub mapper: u8,
+    pub four_screen: bool, //four-screen vram on the cartridge
+    pub mapper: u16,
+    //NES 2.0 fields; iNES 1.0 files get the usual defaults
+    pub nes2: bool,
+    pub submapper: u8,
+    pub console_type: u8,
+    pub prgram_size: usize, //bytes
+    pub prgnvram_size: usize,
+    pub chrram_size: usize,
+    pub chrnvram_size: usize,
+    pub timing: Timing,
+    pub expansion_device: u8,
+    pub trainer: Vec<u8>, //512 bytes for $7000-$71FF, or empty
     pub prgrom: Vec<u8>,
     pub chrrom: Vec<u8>,
-    pub savedata: [u8; 0x2000]
+    pub savedata: Vec<u8>, //initial PRG-RAM contents, sized to all of the board's PRG-RAM
+    pub nsf: Option<NsfHeader>, //set for NSF music files, which get the player board
+    pub crc32: u32, //of PRG+CHR, or of the disk for FDS images
+    pub sha1: String, //hex, same data as crc32
+    pub title: Option<String> //from the game database or NSF header
 }
 impl INES {
-    pub fn new(filename: String,savefile: Option<String>) -> INES {
-        let mut handle = File::open(filename).unwrap();
-        let mut header = [0;16];
-        let mut result = INES {
+    pub fn new(filename: String,savefile: Option<String>) -> Result<INES,RomError> {
+        INES::load(filename,LoadOptions {savefile: savefile,..Default::default()})
+    }
+    pub fn load(filename: String,options: LoadOptions) -> Result<INES,RomError> {
+        let mut data = read_file(&filename)?;
+        if archive::is_archive(&data) {
+            data = archive::extract(&data,options.entry)?;
+        }
+        for path in find_patches(&filename,options.patches) {
+            data = patch::apply(&read_file(&path)?,&data)?;
+        }
+        let mut result = if data.starts_with(b"UNIF") {
+            unif::parse(&data)?
+        } else if nsf::is_nsf(&data) {
+            nsf::parse(&data)?
+        } else if fds::is_fds(&data) {
+            fds::parse(&data,find_bios(&filename,options.bios)?)?
+        } else {
+            INES::from_bytes(&data)?
+        };
+        result.set_hashes();
+        if data.starts_with(b"NES\x1A") && !result.nes2 {
+            gamedb::correct(&mut result);
+        }
+        if options.savefile.is_some() && result.persistent_memory {
+            let path = options.savefile.unwrap();
+            if !Path::new(&path).exists() {
+                let mut handl = File::create(path.clone())?;
+                handl.write_all(&result.savedata)?;
+            }
+            let mut save = vec![];
+            File::open(path)?.read_to_end(&mut save)?;
+            let len = save.len().min(result.savedata.len());
+            result.savedata[..len].copy_from_slice(&save[..len]);
+        }
+        Ok(result)
+    }
+    pub fn empty() -> INES {
+        INES {
             prgrom_size: 0,
             chrrom_size: 0,
             vertical_mirroring: false,
             persistent_memory: false,
-            ignore_mirroring: false,
+            four_screen: false,
             mapper: 0,
+            nes2: false,
+            submapper: 0,
+            console_type: 0,
+            prgram_size: 0,
+            prgnvram_size: 0,
+            chrram_size: 0,
+            chrnvram_size: 0,
+            timing: Timing::NTSC,
+            expansion_device: 0,
+            trainer: vec![],
             prgrom: vec![],
             chrrom: vec![],
-            savedata: [0;0x2000]
-        };
-        handle.read_exact(&mut header).unwrap();
-        result.prgrom_size = header[4];
-        result.chrrom_size = header[5];
+            savedata: vec![],
+            nsf: None,
+            crc32: 0,
+            sha1: String::new(),
+            title: None
+        }
+    }
+    fn set_hashes(&mut self) {
+        let mut data = vec![];
+        if self.mapper == 20 && self.nsf.is_none() {
+            data.extend_from_slice(&self.savedata);
+        } else {
+            data.extend_from_slice(&self.prgrom);
+            if self.chrrom_size != 0 {
+                data.extend_from_slice(&self.chrrom);
+            }
+        }
+        self.crc32 = crc32fast::hash(&data);
+        let mut sha1 = Sha1::new();
+        sha1.update(&data);
+        self.sha1 = sha1.digest().to_string();
+    }
+    pub fn from_bytes(data: &[u8]) -> Result<INES,RomError> {
+        if data.len() < 16 || &data[0..4] != b"NES\x1A" {
+            return Err(RomError::BadMagic);
+        }
+        let header = &data[0..16];
+        let mut result = INES::empty();
         result.vertical_mirroring = header[6] & 0b1 != 0;
         result.persistent_memory = header[6] & 0b10 != 0;
-        result.ignore_mirroring = header[6] & 0b100 != 0;
-        result.mapper = header[7] & 0b11110000 | (header[6] >> 4) & 0b1111;
-        result.prgrom.reserve(0x4000 * result.prgrom_size as usize);
-        unsafe {
-            result.prgrom.set_len(0x4000 * result.prgrom_size as usize);
-        }
-        handle.read(result.prgrom.as_mut_slice()).unwrap();
-        if result.chrrom_size != 0 {
-            result.chrrom.reserve(0x2000 * result.chrrom_size as usize);
-            unsafe {
-                result.chrrom.set_len(0x2000 * result.chrrom_size as usize);
+        result.four_screen = header[6] & 0b1000 != 0;
+        result.mapper = (header[7] & 0b11110000 | (header[6] >> 4) & 0b1111) as u16;
+        result.console_type = header[7] & 0b11;
+        result.nes2 = header[7] & 0b1100 == 0b1000;
+        //iNES 1.0 headers with junk past byte 7 ("DiskDude!") can't be trusted for the mapper's high nibble
+        if !result.nes2 && (header[7] & 0b1100 == 0b0100 || header[12..16].iter().any(|&x| x != 0)) {
+            result.mapper &= 0b1111;
+            result.console_type = 0;
+        }
+        let prg_bytes;
+        let chr_bytes;
+        if result.nes2 {
+            result.mapper |= ((header[8] & 0b1111) as u16) << 8;
+            result.submapper = header[8] >> 4;
+            //sizes too big to even count can't be in the file either
+            prg_bytes = nes2_rom_size(header[4],header[9] & 0b1111,0x4000).ok_or(RomError::TruncatedPrg)?;
+            chr_bytes = nes2_rom_size(header[5],header[9] >> 4,0x2000).ok_or(RomError::TruncatedChr)?;
+            result.prgram_size = nes2_ram_size(header[10] & 0b1111);
+            result.prgnvram_size = nes2_ram_size(header[10] >> 4);
+            result.chrram_size = nes2_ram_size(header[11] & 0b1111);
+            result.chrnvram_size = nes2_ram_size(header[11] >> 4);
+            result.timing = match header[12] & 0b11 {
+                0 => Timing::NTSC,
+                1 => Timing::PAL,
+                2 => Timing::Multiple,
+                _ => Timing::Dendy
+            };
+            result.expansion_device = header[15] & 0b111111;
+        } else {
+            prg_bytes = 0x4000 * header[4] as usize;
+            chr_bytes = 0x2000 * header[5] as usize;
+            let ram = ines1_prgram_size(result.mapper);
+            if result.persistent_memory {
+                result.prgnvram_size = ram;
+            } else {
+                result.prgram_size = ram;
+            }
+            if chr_bytes == 0 {
+                result.chrram_size = 0x2000;
             }
-            handle.read(result.chrrom.as_mut_slice()).unwrap();
+        }
+        result.savedata = vec![0;result.prgram_size + result.prgnvram_size + board_nvram_size(result.mapper)];
+        result.prgrom_size = ((prg_bytes + 0x3FFF) / 0x4000) as u16;
+        result.chrrom_size = ((chr_bytes + 0x1FFF) / 0x2000) as u16;
+        let mut prg_start: usize = 16;
+        if header[6] & 0b100 != 0 {
+            if data.len() < 16 + 512 {
+                return Err(RomError::TruncatedPrg);
+            }
+            result.trainer = data[16..16 + 512].to_vec();
+            prg_start += 512;
+        }
+        //every board needs something to run, and would divide by zero without it
+        if prg_bytes == 0 {
+            return Err(RomError::TruncatedPrg);
+        }
+        let prg_end = match prg_start.checked_add(prg_bytes) {
+            Some(end) if end <= data.len() => end,
+            _ => return Err(RomError::TruncatedPrg)
+        };
+        result.prgrom = data[prg_start..prg_end].to_vec();
+        //NES 2.0 can give sizes under 16 KiB, which boards see mirrored
+        while result.prgrom.len() < 0x4000 {
+            let copy = result.prgrom.clone();
+            result.prgrom.extend_from_slice(&copy);
+        }
+        if chr_bytes != 0 {
+            let chr_end = match prg_end.checked_add(chr_bytes) {
+                Some(end) if end <= data.len() => end,
+                _ => return Err(RomError::TruncatedChr)
+            };
+            result.chrrom = data[prg_end..chr_end].to_vec();
         }
         if result.chrrom_size == 0 {
-            result.chrrom = vec![0;0x2000*32];
+            result.chrrom = vec![0;(result.chrram_size + result.chrnvram_size).max(0x2000)];
         }
-        if savefile.is_some() && result.persistent_memory {
-            let path = savefile.unwrap();
-            if !Path::new(&path).exists() {
-                let mut handl = File::create(path.clone()).unwrap();
-                handl.write(&result.savedata).unwrap();
-            }
-            let mut handle = File::open(path).unwrap();
-            handle.read(&mut result.savedata).unwrap();
+        Ok(result)
+    }
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>,RomError> {
+    let mut data = vec![];
+    match File::open(filename) {
+        Ok(mut handle) => {
+            handle.read_to_end(&mut data)?;
         }
-        result
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(RomError::MissingFile(filename.to_string())),
+        Err(e) => return Err(RomError::Io(e))
+    }
+    Ok(data)
+}
+
+fn find_patches(filename: &str,patches: Vec<String>) -> Vec<String> {
+    if patches.len() != 0 {
+        return patches;
+    }
+    for extension in ["ips","ups","bps"].iter() {
+        let path = Path::new(filename).with_extension(extension);
+        if path.exists() {
+            return vec![path.to_string_lossy().to_string()];
+        }
+    }
+    vec![]
+}
+
+fn find_bios(filename: &str,bios: Option<String>) -> Result<Vec<u8>,RomError> {
+    if let Some(bios) = bios {
+        return read_file(&bios);
+    }
+    let beside = Path::new(filename).with_file_name("disksys.rom");
+    for path in [beside.as_path(),Path::new("disksys.rom")].iter() {
+        if path.exists() {
+            return read_file(&path.to_string_lossy());
+        }
+    }
+    Err(RomError::MissingBios)
+}
+
+//NES 2.0 rom sizes: a 12 bit unit count, or 2^E*(MM*2+1) bytes when the high nibble is $F
+//None when it doesn't fit in a usize
+fn nes2_rom_size(lsb: u8,msb: u8,unit: usize) -> Option<usize> {
+    if msb == 0xF {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        1usize.checked_shl(exponent).and_then(|x| x.checked_mul(multiplier))
+    } else {
+        (((msb as usize) << 8) | lsb as usize).checked_mul(unit)
+    }
+}
+
+//iNES 1.0 can't say how much PRG-RAM there is: 8 KiB, or 64 KiB for MMC5 boards which had up to that
+pub fn ines1_prgram_size(mapper: u16) -> usize {
+    if mapper == 5 { 0x10000 } else { 0x2000 }
+}
+
+//NES 2.0 ram sizes are shift counts: 64 << n, with 0 meaning none
+fn nes2_ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+//battery backable memory some boards have besides PRG-RAM, kept after it in savedata
+pub fn board_nvram_size(mapper: u16) -> usize {
+    match mapper {
+        16 => 0x100, //Bandai 24C02 EEPROM
+        19 => 0x80, //Namco 163 internal RAM
+        157 => 0x180, //Datach 24C02, then the game cartridge's 24C01
+        159 => 0x80, //Bandai 24C01 EEPROM
+        _ => 0
     }
 }
@@ -0,0 +1,25 @@
+extern crate bincode;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate crc32fast;
+extern crate flate2;
+extern crate zip;
+extern crate sha1_smol;
+mod instruction;
+pub mod cpu;
+pub mod apu;
+pub mod ppu;
+pub mod ines;
+pub mod unif;
+pub mod fds;
+pub mod nsf;
+pub mod patch;
+pub mod archive;
+pub mod gamedb;
+pub mod mappers;
+pub mod expansion;
+pub mod emulator;
+mod test;
+
+pub use emulator::Emulator;
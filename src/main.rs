@@ -1,27 +1,54 @@
+extern crate rsnes;
+#[cfg(feature="sdl")]
 extern crate sdl2;
-extern crate bincode;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde;
-mod instruction;
-mod cpu;
-mod apu;
-mod ppu;
-mod ines;
-mod mappers;
+#[cfg(feature="sdl")]
 mod nes;
-mod test;
 
-use cpu::*;
-use nes::NES;
+use rsnes::cpu::*;
+use rsnes::Emulator;
+use rsnes::mappers::N163;
+use rsnes::mappers::VRC24;
+use rsnes::ines::LoadOptions;
+use rsnes::ines::INES;
 
 pub fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let file = args[1].to_string();
-    let save = if args.len() <3 {None} else {Some(args[2].to_string())};
+    let mut args = std::env::args().skip(1);
+    let mut positional = vec![];
+    let mut options = LoadOptions::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bios" => options.bios = args.next(),
+            "--patch" => options.patches.extend(args.next()),
+            "--entry" => options.entry = args.next(),
+            _ => positional.push(arg)
+        }
+    }
+    if positional.len() == 0 {
+        eprintln!("usage: rsnes [--bios disksys.rom] [--patch file]... [--entry name] [filename] [(optional) savefile]");
+        std::process::exit(1);
+    }
+    let file = positional[0].to_string();
+    options.savefile = positional.get(1).cloned();
     if file == "TEST" {
         CPU::test();
+        CPU::test_interrupts();
+        Emulator::test();
+        N163::test();
+        VRC24::test();
+        INES::test();
+        INES::test_patches();
     } else {
-        NES::start(file,save);
+        play(file,options);
     }
 }
+
+#[cfg(feature="sdl")]
+fn play(file: String,options: LoadOptions) {
+    nes::NES::start(file,options);
+}
+
+#[cfg(not(feature="sdl"))]
+fn play(_file: String,_options: LoadOptions) {
+    eprintln!("rsnes was built without the sdl feature, so there is no window to play in. Only TEST is available.");
+    std::process::exit(1);
+}
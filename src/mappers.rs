@@ -1,55 +1,165 @@
 use ines::INES;
+use ines::RomError;
+use ines;
+use expansion::FdsAudio;
+use expansion::Mmc5Audio;
+use expansion::Sunsoft5bAudio;
+use expansion::Vrc7Audio;
+use fds;
+use nsf;
+use nsf::NsfHeader;
 use std::rc::Rc;
 use std::cell::RefCell;
-use bincode::*;
+use std::cmp;
+use bincode::{serialize,deserialize};
+
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen
+}
+
+//writes to the expansion sound chips emulated by the blargg library
+pub enum ChipWrite {
+    Vrc6 {osc: i32,reg: i32,what: u8},
+    Namco {address: i32,what: u8}
+}
+
+//which of the four 1 KiB nametables a $2000-$3EFF access lands in
+fn nametable(location:usize,mirroring:Mirroring) -> usize {
+    let table = (location >> 10) & 3;
+    match mirroring {
+        Mirroring::Horizontal => table >> 1,
+        Mirroring::Vertical => table & 1,
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => table
+    }
+}
+
+//PRG-RAM smaller than the 8 KiB window is mirrored; boards without any read back 0
+fn prgram_read(prgram:&[u8],offset:usize) -> u8 {
+    if prgram.len() == 0 {
+        0
+    } else {
+        prgram[offset % prgram.len()]
+    }
+}
+
+fn prgram_write(prgram:&mut [u8],offset:usize,what:u8) {
+    if prgram.len() != 0 {
+        let len = prgram.len();
+        prgram[offset % len] = what;
+    }
+}
+
+//CHR-RAM is part of the mapper state; CHR-ROM never changes so it is left out
+fn chrram(ines:&INES) -> Vec<u8> {
+    if ines.chrrom_size == 0 {
+        ines.chrrom.clone()
+    } else {
+        vec![]
+    }
+}
+
+fn set_chrram(ines:&mut INES,data:&[u8]) {
+    if ines.chrrom_size == 0 && ines.chrrom.len() == data.len() {
+        ines.chrrom.copy_from_slice(data);
+    }
+}
+
+//mirroring soldered on the board, for mappers that can't change it
+fn header_mirroring(ines:&INES) -> Mirroring {
+    if ines.four_screen {
+        Mirroring::FourScreen
+    } else if ines.vertical_mirroring {
+        Mirroring::Vertical
+    } else {
+        Mirroring::Horizontal
+    }
+}
 
 pub trait Mapper {
     fn contents(&mut self,index:u16) -> u8;
     fn set_contents(&mut self,index:u16,what:u8);
     fn vram_contents(&mut self,index:u16) -> u8;
     fn set_vram_contents(&mut self,index:u16,what:u8);
-    fn get_savedata(&mut self) -> [u8; 0x2000];
-    fn scanline(&mut self) { //true = IRQ false = no IRQ
+    fn get_savedata(&self) -> &[u8];
+    //every address the PPU puts on its bus, for boards that watch the lines themselves
+    fn ppu_address(&mut self,_address:u16) {
 
     }
     fn interrupt(&mut self) -> bool {
         false
     }
+    fn cpu_cycle(&mut self) {
+
+    }
+    //sound chip register writes since the last call, for the emulator to pass to the APU
+    fn chip_writes(&mut self) -> Vec<ChipWrite> {
+        vec![]
+    }
+    //the PPU starts fetching sprite patterns (dot 257) or goes back to background tiles
+    //for the next line (dot 321) on a rendered scanline
+    fn ppu_fetch(&mut self,_scanline:u16,_sprites:bool,_big_sprites:bool) {
+
+    }
+    //expansion audio level for apu_expansion
+    fn audio_output(&self) -> i32 {
+        0
+    }
+    fn switch_disk_side(&mut self) {
+
+    }
+    //NSF only: move delta tracks, giving the 1 based track and track count
+    fn change_track(&mut self,_delta:i32) -> Option<(usize,usize)> {
+        None
+    }
     fn serialize(&self) -> Vec<u8>;
     fn deserialize(&mut self,data:&[u8]);
 }
 
 struct NROM {
     ines: INES,
-    prgram: [u8;0x2000],
-    nametables:[[u8;0x400];2]
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4]
 }
 
 #[derive(Serialize,Deserialize)]
 struct NROM_Serial {
     prgram: Vec<u8>,
+    chrram: Vec<u8>,
     nametables: Vec<Vec<u8>>
 }
 
+impl NROM {
+    fn mirroring(&self) -> Mirroring {
+        header_mirroring(&self.ines)
+    }
+}
+
 impl Mapper for NROM {
     fn serialize(&self) -> Vec<u8> {
         let mut vprgram = vec![];
         vprgram.extend_from_slice(&self.prgram);
-        let mut vnametables = vec![vec![],vec![]];
-        vnametables[0].extend_from_slice(&self.nametables[0]);
-        vnametables[1].extend_from_slice(&self.nametables[1]);
-        serialize(&NROM_Serial {prgram: vprgram, nametables: vnametables}).unwrap()
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        serialize(&NROM_Serial {prgram: vprgram, chrram: chrram(&self.ines), nametables: vnametables}).unwrap()
     }
     fn deserialize(&mut self,data:&[u8]) {
         let x: NROM_Serial = deserialize(data).unwrap();
         self.prgram.copy_from_slice(&x.prgram);
-        self.nametables[0].copy_from_slice(&x.nametables[0]);
-        self.nametables[1].copy_from_slice(&x.nametables[1]);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
     }
     fn contents(&mut self,location:u16) -> u8 {
         let mut location = location as usize;
         match location {
-            0x6000...0x7FFF => self.prgram[location - 0x6000],
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
             0x8000...0xFFFF => {
                 if self.ines.prgrom_size == 1 { location &= 0xBFFF; }
                 self.ines.prgrom[location - 0x8000]
@@ -59,7 +169,7 @@ impl Mapper for NROM {
     }
     fn set_contents(&mut self,location:u16,what:u8) {
         match location {
-            0x6000...0x7FFF => self.prgram[location as usize - 0x6000] = what,
+            0x6000...0x7FFF => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
             _ => ()
         }
     }
@@ -68,14 +178,7 @@ impl Mapper for NROM {
         match location {
             0...0x1FFF => self.ines.chrrom[location],
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => 0,
-                    0x400...0x7FF => (self.ines.vertical_mirroring) as usize,
-                    0x800...0xBFF => (!self.ines.vertical_mirroring) as usize,
-                    0xC00...0xFFF => 1,
-                    _ => 2
-                };
+                let nametable = nametable(location,self.mirroring());
                 location &= 0x3FF;
                 self.nametables[nametable][location]
             }
@@ -89,22 +192,15 @@ impl Mapper for NROM {
                 self.ines.chrrom[location] = what;
             }
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => 0,
-                    0x400...0x7FF => (self.ines.vertical_mirroring) as usize,
-                    0x800...0xBFF => (!self.ines.vertical_mirroring) as usize,
-                    0xC00...0xFFF => 1,
-                    _ => 2
-                };
+                let nametable = nametable(location,self.mirroring());
                 location &= 0x3FF;
                 self.nametables[nametable][location] = what;
             }
             _ => ()
         }
     }
-    fn get_savedata(&mut self) -> [u8; 0x2000] {
-        self.prgram.clone()
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
     }
 }
 
@@ -120,8 +216,8 @@ struct MMC1 {
     enableprgram: bool,
     prgbank: usize,
 
-    prgram: [u8;0x2000],
-    nametables:[[u8;0x400];2]
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4]
 }
 
 #[derive(Serialize,Deserialize)]
@@ -135,16 +231,50 @@ struct MMC1_Serial {
     enableprgram: bool,
     prgbank: usize,
     prgram: Vec<u8>,
+    chrram: Vec<u8>,
     nametables: Vec<Vec<u8>>
 }
 
+impl MMC1 {
+    fn mirroring(&self) -> Mirroring {
+        if self.ines.four_screen {
+            return Mirroring::FourScreen;
+        }
+        match self.mirroringtype {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal
+        }
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        let index = match self.chrrommode {
+            0 => 0x2000*(self.chr0bank >> 1) + location,
+            _ => {
+                if location < 0x1000 {
+                    0x1000*self.chr0bank + location
+                } else {
+                    0x1000*self.chr1bank + location - 0x1000
+                }
+            }
+        };
+        index % self.ines.chrrom.len()
+    }
+    //SOROM and SXROM take their 8 KiB PRG-RAM bank from the CHR bank register
+    fn prgram_bank(&self) -> usize {
+        match self.prgram.len() {
+            0x4000 => (self.chr0bank >> 3) & 1,
+            0x8000 => (self.chr0bank >> 2) & 3,
+            _ => 0
+        }
+    }
+}
+
 impl Mapper for MMC1 {
     fn serialize(&self) -> Vec<u8> {
         let mut vprgram = vec![];
         vprgram.extend_from_slice(&self.prgram);
-        let mut vnametables = vec![vec![],vec![]];
-        vnametables[0].extend_from_slice(&self.nametables[0]);
-        vnametables[1].extend_from_slice(&self.nametables[1]);
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
         let serial = MMC1_Serial {
             input:self.input,
             mirroringtype:self.mirroringtype,
@@ -155,6 +285,7 @@ impl Mapper for MMC1 {
             enableprgram:self.enableprgram,
             prgbank:self.prgbank,
             prgram:vprgram,
+            chrram:chrram(&self.ines),
             nametables:vnametables
         };
         serialize(&serial).unwrap()
@@ -170,14 +301,18 @@ impl Mapper for MMC1 {
         self.enableprgram = x.enableprgram;
         self.prgbank = x.prgbank;
         self.prgram.copy_from_slice(&x.prgram);
-        self.nametables[0].copy_from_slice(&x.nametables[0]);
-        self.nametables[1].copy_from_slice(&x.nametables[1]);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
     }
     fn contents(&mut self,location:u16) -> u8 {
         let mut location = location as usize;
         match location {
-            0x6000...0x7FFF =>
-                self.prgram[location - 0x6000],
+            0x6000...0x7FFF => {
+                let bank = self.prgram_bank();
+                prgram_read(&self.prgram,0x2000*bank + location - 0x6000)
+            }
             0x8000...0xFFFF => {
                 location -= 0x8000;
                 match self.prgrommode {
@@ -207,7 +342,8 @@ impl Mapper for MMC1 {
     fn set_contents(&mut self,location:u16,what:u8) {
         match location {
             0x6000...0x7FFF => {
-                self.prgram[location as usize - 0x6000] = what;
+                let bank = self.prgram_bank();
+                prgram_write(&mut self.prgram,0x2000*bank + location as usize - 0x6000,what);
             }
             0x8000...0xFFFF => {
                 if what & 0x80 != 0 {
@@ -247,29 +383,11 @@ impl Mapper for MMC1 {
         let mut location = location as usize;
         match location {
             0...0x1FFF => {
-                match self.chrrommode {
-                    0 => {
-                        self.ines.chrrom[0x2000*(self.chr0bank >> 1) + location]
-                    }
-                    1 => {
-                        if location < 0x1000 {
-                            self.ines.chrrom[0x1000*self.chr0bank + location]
-                        } else {
-                            self.ines.chrrom[0x1000*self.chr1bank + location - 0x1000]
-                        }
-                    }
-                    _ => 0
-                }
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
             }
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => (self.mirroringtype == 1) as usize,
-                    0x400...0x7FF => (self.mirroringtype == 2 || self.mirroringtype == 1) as usize,
-                    0x800...0xBFF => (self.mirroringtype == 1 || self.mirroringtype == 3) as usize,
-                    0xC00...0xFFF => (self.mirroringtype != 0) as usize,
-                    _ => 2
-                };
+                let nametable = nametable(location,self.mirroring());
                 location &= 0x3FF;
                 self.nametables[nametable][location]
             }
@@ -280,226 +398,89 @@ impl Mapper for MMC1 {
         let mut location = location as usize;
         match location {
             0...0x1FFF if self.ines.chrrom_size == 0 => {
-                match self.chrrommode {
-                    0 => {
-                        self.ines.chrrom[0x2000*(self.chr0bank >> 1) + location] = what
-                    }
-                    1 => {
-                        if location < 0x1000 {
-                            self.ines.chrrom[0x1000*self.chr0bank + location] = what
-                        } else {
-                            self.ines.chrrom[0x1000*self.chr1bank + location - 0x1000] = what
-                        }
-                    }
-                    _ => ()
-                }
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
             }
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => (self.mirroringtype == 1) as usize,
-                    0x400...0x7FF => (self.mirroringtype == 2 || self.mirroringtype == 1) as usize,
-                    0x800...0xBFF => (self.mirroringtype == 1 || self.mirroringtype == 3) as usize,
-                    0xC00...0xFFF => (self.mirroringtype != 0) as usize,
-                    _ => 2
-                };
+                let nametable = nametable(location,self.mirroring());
                 location &= 0x3FF;
                 self.nametables[nametable][location] = what;
             }
             _ => ()
         }
     }
-    fn get_savedata(&mut self) -> [u8; 0x2000] {
-        self.prgram.clone()
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
     }
 }
 
-pub struct MMC3 {
+//UxROM (2) and UN1ROM (94): a 16 KiB bank at $8000 and the last bank fixed at $C000
+struct UxROM {
     ines: INES,
-    prgram: [u8;0x2000],
-    nametables:[[u8;0x400];2],
-    inputselect: u8,
-    prgrommode: bool,
-    chrrommode: bool,
-    chrbank0: usize,
-    chrbank1: usize,
-    chrbank2: usize,
-    chrbank3: usize,
-    chrbank4: usize,
-    chrbank5: usize,
-    prgbank0: usize,
-    prgbank1: usize,
-    horizontalmirroring: bool,
-    irqreload: u8,
-    irqcounter: u8,
-    generate_irq: bool,
-    interrupt: bool
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    prgbank: usize,
+    un1rom: bool, //the bank number sits in bits 2-4
+    bus_conflicts: bool
 }
+
 #[derive(Serialize,Deserialize)]
-struct MMC3_Serial {
+struct UxROM_Serial {
     prgram: Vec<u8>,
+    chrram: Vec<u8>,
     nametables: Vec<Vec<u8>>,
-    inputselect: u8,
-    prgrommode: bool,
-    chrrommode: bool,
-    chrbank0: usize,
-    chrbank1: usize,
-    chrbank2: usize,
-    chrbank3: usize,
-    chrbank4: usize,
-    chrbank5: usize,
-    prgbank0: usize,
-    prgbank1: usize,
-    horizontalmirroring: bool,
-    irqreload: u8,
-    irqcounter: u8,
-    generate_irq: bool,
-    interrupt: bool
+    prgbank: usize
 }
 
-impl Mapper for MMC3 {
+impl Mapper for UxROM {
     fn serialize(&self) -> Vec<u8> {
-        let mut vprgram = vec![];
-        vprgram.extend_from_slice(&self.prgram);
-        let mut vnametables = vec![vec![],vec![]];
-        vnametables[0].extend_from_slice(&self.nametables[0]);
-        vnametables[1].extend_from_slice(&self.nametables[1]);
-        let serial = MMC3_Serial {
-            prgram: vprgram,
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = UxROM_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
             nametables: vnametables,
-            inputselect: self.inputselect,
-            prgrommode: self.prgrommode,
-            chrrommode: self.chrrommode,
-            chrbank0: self.chrbank0,
-            chrbank1: self.chrbank1,
-            chrbank2: self.chrbank2,
-            chrbank3: self.chrbank3,
-            chrbank4: self.chrbank4,
-            chrbank5: self.chrbank5,
-            prgbank0: self.prgbank0,
-            prgbank1: self.prgbank1,
-            horizontalmirroring: self.horizontalmirroring,
-            irqreload: self.irqreload,
-            irqcounter: self.irqcounter,
-            generate_irq: self.generate_irq,
-            interrupt: self.interrupt
+            prgbank: self.prgbank
         };
         serialize(&serial).unwrap()
     }
     fn deserialize(&mut self,data:&[u8]) {
-        let x: MMC3_Serial = deserialize(data).unwrap();
+        let x: UxROM_Serial = deserialize(data).unwrap();
         self.prgram.copy_from_slice(&x.prgram);
-        self.nametables[0].copy_from_slice(&x.nametables[0]);
-        self.nametables[1].copy_from_slice(&x.nametables[1]);
-        self.inputselect = x.inputselect;
-        self.prgrommode = x.prgrommode;
-        self.chrrommode = x.chrrommode;
-        self.chrbank0 = x.chrbank0;
-        self.chrbank1 = x.chrbank1;
-        self.chrbank2 = x.chrbank2;
-        self.chrbank3 = x.chrbank3;
-        self.chrbank4 = x.chrbank4;
-        self.chrbank5 = x.chrbank5;
-        self.prgbank0 = x.prgbank0;
-        self.prgbank1 = x.prgbank1;
-        self.horizontalmirroring = x.horizontalmirroring;
-        self.irqreload = x.irqreload;
-        self.irqcounter = x.irqcounter;
-        self.generate_irq = x.generate_irq;
-        self.interrupt = x.interrupt;
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbank = x.prgbank;
     }
     fn contents(&mut self,location:u16) -> u8 {
         let location = location as usize;
-        let prgrom_size = self.ines.prgrom_size as usize * 2;
+        let len = self.ines.prgrom.len();
+        //an 8 KiB ROM still counts as one bank, mirrored across it
+        let banks = cmp::max(len / 0x4000,1);
         match location {
-            0x6000...0x7FFF => self.prgram[location - 0x6000],
-            0x8000...0x9FFF => {
-                if self.prgrommode {
-                    self.ines.prgrom[0x2000*(prgrom_size - 2) + location - 0x8000]
-                } else {
-                    self.ines.prgrom[0x2000*self.prgbank0 + location - 0x8000]
-                }
-            }
-            0xA000...0xBFFF => self.ines.prgrom[0x2000*self.prgbank1 + location - 0xA000],
-            0xC000...0xDFFF => {
-                if !self.prgrommode {
-                    self.ines.prgrom[0x2000*(prgrom_size - 2) + location - 0xC000]
-                } else {
-                    self.ines.prgrom[0x2000*self.prgbank0 + location - 0xC000]
-                }
-            }
-            0xE000...0xFFFF => self.ines.prgrom[0x2000*(prgrom_size - 1) + location - 0xE000],
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
+            0x8000...0xBFFF => self.ines.prgrom[(0x4000*(self.prgbank % banks) + location - 0x8000) % len],
+            0xC000...0xFFFF => self.ines.prgrom[(0x4000*(banks - 1) + location - 0xC000) % len],
             _ => 0
         }
     }
     fn set_contents(&mut self,location:u16,what:u8) {
         match location {
-            0x6000...0x7FFF => self.prgram[location as usize - 0x6000] = what,
-            0x8000...0x9FFF => {
-                if location & 1 == 0 {
-                    self.inputselect = what & 0x7;
-                    self.prgrommode = what & 0x40 != 0;
-                    self.chrrommode = what & 0x80 != 0;
-                } else {
-                    let what = what as usize;
-                    match self.inputselect {
-                        0 => self.chrbank0 = what >> 1,
-                        1 => self.chrbank1 = what >> 1,
-                        2 => self.chrbank2 = what,
-                        3 => self.chrbank3 = what,
-                        4 => self.chrbank4 = what,
-                        5 => self.chrbank5 = what,
-                        6 => self.prgbank0 = what & 0x3F,
-                        7 => self.prgbank1 = what & 0x3F,
-                        _ => ()
-                    }
-                }
-            }
-            0xA000...0xBFFF => {
-                if location & 1 == 0 {
-                    self.horizontalmirroring = what & 1 != 0;
-                } else {
-                    () //not neccesary to emulate; ram write protection
-                }
-            }
-            0xC000...0xDFFF => {
-                if location & 1 == 0 {
-                    self.irqreload = what;
-                } else {
-                    self.irqcounter = 0;
-                }
-            }
-            0xE000...0xEFFF => {
-                if location & 1 == 0 {
-                    self.generate_irq = false;
-                } else {
-                    self.generate_irq = true;
-                }
+            0x6000...0x7FFF => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
+            0x8000...0xFFFF => {
+                //the ROM drives the bus too, so only bits both agree on get through
+                let what = if self.bus_conflicts { what & self.contents(location) } else { what };
+                self.prgbank = if self.un1rom { (what >> 2) & 7 } else { what } as usize;
             }
             _ => ()
         }
     }
     fn vram_contents(&mut self,location:u16) -> u8 {
         let mut location = location as usize;
-        if self.chrrommode {
-            location ^= 0x1000;
-        }
         match location {
-            0x0...0x7FF => self.ines.chrrom[0x800*self.chrbank0 + location],
-            0x800...0xFFF => self.ines.chrrom[0x800*self.chrbank1 + location - 0x800],
-            0x1000...0x13FF => self.ines.chrrom[0x400*self.chrbank2 + location - 0x1000],
-            0x1400...0x17FF => self.ines.chrrom[0x400*self.chrbank3 + location - 0x1400],
-            0x1800...0x1BFF => self.ines.chrrom[0x400*self.chrbank4 + location - 0x1800],
-            0x1C00...0x1FFF => self.ines.chrrom[0x400*self.chrbank5 + location - 0x1C00],
+            0...0x1FFF => self.ines.chrrom[location],
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => 0,
-                    0x400...0x7FF => (!self.horizontalmirroring) as usize,
-                    0x800...0xBFF => self.horizontalmirroring as usize,
-                    0xC00...0xFFF => 1,
-                    _ => 2
-                };
+                let nametable = nametable(location,header_mirroring(&self.ines));
                 location &= 0x3FF;
                 self.nametables[nametable][location]
             }
@@ -509,92 +490,3526 @@ impl Mapper for MMC3 {
     fn set_vram_contents(&mut self,location:u16,what:u8) {
         let mut location = location as usize;
         match location {
-            0x0...0x7FF => self.ines.chrrom[0x800*self.chrbank0 + location] = what,
-            0x800...0xFFF => self.ines.chrrom[0x800*self.chrbank1 + location - 0x800] = what,
-            0x1000...0x13FF => self.ines.chrrom[0x400*self.chrbank2 + location - 0x1000] = what,
-            0x1400...0x17FF => self.ines.chrrom[0x400*self.chrbank3 + location - 0x1400] = what,
-            0x1800...0x1BFF => self.ines.chrrom[0x400*self.chrbank4 + location - 0x1800] = what,
-            0x1C00...0x1FFF => self.ines.chrrom[0x400*self.chrbank5 + location - 0x1C00] = what,
+            0...0x1FFF if self.ines.chrrom_size == 0 => self.ines.chrrom[location] = what,
             0x2000...0x3EFF => {
-                location &= 0xFFF;
-                let nametable:usize = match location {
-                    0...0x3FF => 0,
-                    0x400...0x7FF => (!self.horizontalmirroring) as usize,
-                    0x800...0xBFF => self.horizontalmirroring as usize,
-                    0xC00...0xFFF => 1,
-                    _ => 2
-                };
+                let nametable = nametable(location,header_mirroring(&self.ines));
                 location &= 0x3FF;
                 self.nametables[nametable][location] = what;
             }
             _ => ()
         }
     }
-    fn interrupt(&mut self) -> bool {
-        let old = self.interrupt;
-        self.interrupt = false;
-        old
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
     }
-    fn scanline(&mut self) {
-        if self.irqcounter == 0 {
-            self.irqcounter = self.irqreload;
-        } else {
-            self.irqcounter -= 1;
-            if self.irqcounter == 0 && self.generate_irq {
-                self.interrupt = true;
-            }
-        }
+}
+
+//boards built from a latch or two, told apart by the mapper number:
+//CNROM (3), AxROM (7), Color Dreams (11), BNROM/NINA-001 (34) and GxROM (66)
+struct Discrete {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    prgbank: usize, //32 KiB
+    chrbank0: usize, //4 KiB at $0000
+    chrbank1: usize, //4 KiB at $1000
+    mirroring: Mirroring,
+    nina: bool, //NINA-001 has its registers at $7FFD-$7FFF
+    bus_conflicts: bool
+}
+
+#[derive(Serialize,Deserialize)]
+struct Discrete_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbank: usize,
+    chrbank0: usize,
+    chrbank1: usize,
+    mirroring: Mirroring
+}
+
+impl Discrete {
+    fn set_chr8(&mut self,bank:usize) {
+        self.chrbank0 = bank*2;
+        self.chrbank1 = bank*2 + 1;
     }
-    fn get_savedata(&mut self) -> [u8; 0x2000] {
-        self.prgram.clone()
+    fn chr_index(&self,location:usize) -> usize {
+        let index = if location < 0x1000 {
+            0x1000*self.chrbank0 + location
+        } else {
+            0x1000*self.chrbank1 + location - 0x1000
+        };
+        index % self.ines.chrrom.len()
     }
 }
 
-pub fn get_mapper(mut ines:INES) -> Rc<RefCell<Mapper>> {
-    let prgram = ines.savedata.clone();
-    match ines.mapper {
-        0 => Rc::new(RefCell::new(NROM {ines:ines,prgram:prgram,nametables:[[0;0x400];2]})),
-        1 => {
-            Rc::new(RefCell::new(
-                MMC1 {
-                    ines:ines,
-                    prgram:prgram,
-                    nametables:[[0;0x400];2],
-                    mirroringtype:0,
-                    prgrommode:3,
-                    chrrommode:0,
-                    chr0bank: 0,
-                    chr1bank: 0,
-                    enableprgram: true,
-                    prgbank: 0,
-                    input: 1 << 4,
-                }))
+impl Mapper for Discrete {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = Discrete_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbank: self.prgbank,
+            chrbank0: self.chrbank0,
+            chrbank1: self.chrbank1,
+            mirroring: self.mirroring
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: Discrete_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
         }
-        4 => {
-            Rc::new(RefCell::new({
-                MMC3 {
-                    ines: ines,
-                    prgram: prgram,
-                    nametables:[[0;0x400];2],
-                    inputselect: 0,
-                    prgrommode: false,
-                    chrrommode: false,
-                    chrbank0: 0,
-                    chrbank1: 0,
-                    chrbank2: 0,
-                    chrbank3: 0,
-                    chrbank4: 0,
-                    chrbank5: 0,
-                    prgbank0: 0,
-                    prgbank1: 0,
-                    horizontalmirroring: false,
-                    irqreload: 0,
-                    irqcounter: 0,
-                    generate_irq: false,
-                    interrupt: false,
-                }
-            }))
+        self.prgbank = x.prgbank;
+        self.chrbank0 = x.chrbank0;
+        self.chrbank1 = x.chrbank1;
+        self.mirroring = x.mirroring;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        match location {
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
+            //16 KiB boards are mirrored
+            0x8000...0xFFFF => self.ines.prgrom[(0x8000*self.prgbank + location - 0x8000) % self.ines.prgrom.len()],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x6000...0x7FFF => {
+                prgram_write(&mut self.prgram,location as usize - 0x6000,what);
+                if self.nina {
+                    match location {
+                        0x7FFD => self.prgbank = (what & 1) as usize,
+                        0x7FFE => self.chrbank0 = (what & 0xF) as usize,
+                        0x7FFF => self.chrbank1 = (what & 0xF) as usize,
+                        _ => ()
+                    }
+                }
+            }
+            0x8000...0xFFFF if !self.nina => {
+                //the ROM drives the bus too, so only bits both agree on get through
+                let what = if self.bus_conflicts { what & self.contents(location) } else { what } as usize;
+                match self.ines.mapper {
+                    3 => self.set_chr8(what),
+                    7 => {
+                        self.prgbank = what & 7;
+                        self.mirroring = if what & 0x10 != 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+                    }
+                    11 => {
+                        self.prgbank = what & 3;
+                        self.set_chr8(what >> 4);
+                    }
+                    34 => self.prgbank = what,
+                    66 => {
+                        self.prgbank = (what >> 4) & 3;
+                        self.set_chr8(what & 3);
+                    }
+                    _ => ()
+                }
+            }
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring);
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring);
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//MMC2 (9) and MMC4 (10): two 4 KiB CHR banks, each picked by a latch that flips
+//when the PPU fetches tile $FD or $FE from that pattern table
+struct MMC2 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    mmc4: bool,
+    prgbank: usize,
+    chrbanks: [usize;4], //$0000 FD, $0000 FE, $1000 FD, $1000 FE
+    latch0: bool, //true after $FE
+    latch1: bool,
+    horizontalmirroring: bool
+}
+
+#[derive(Serialize,Deserialize)]
+struct MMC2_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbank: usize,
+    chrbanks: [usize;4],
+    latch0: bool,
+    latch1: bool,
+    horizontalmirroring: bool
+}
+
+impl MMC2 {
+    fn mirroring(&self) -> Mirroring {
+        if self.ines.four_screen {
+            Mirroring::FourScreen
+        } else if self.horizontalmirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        let bank = if location < 0x1000 {
+            self.chrbanks[self.latch0 as usize]
+        } else {
+            self.chrbanks[2 + self.latch1 as usize]
+        };
+        (0x1000*bank + (location & 0xFFF)) % self.ines.chrrom.len()
+    }
+    //the latches change after the fetch that trips them; MMC2 only watches one byte of the left table
+    fn update_latches(&mut self,location:usize) {
+        match location {
+            0x0FD8 => self.latch0 = false,
+            0x0FE8 => self.latch0 = true,
+            0x0FD9...0x0FDF if self.mmc4 => self.latch0 = false,
+            0x0FE9...0x0FEF if self.mmc4 => self.latch0 = true,
+            0x1FD8...0x1FDF => self.latch1 = false,
+            0x1FE8...0x1FEF => self.latch1 = true,
+            _ => ()
+        }
+    }
+}
+
+impl Mapper for MMC2 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = MMC2_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbank: self.prgbank,
+            chrbanks: self.chrbanks,
+            latch0: self.latch0,
+            latch1: self.latch1,
+            horizontalmirroring: self.horizontalmirroring
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: MMC2_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbank = x.prgbank;
+        self.chrbanks = x.chrbanks;
+        self.latch0 = x.latch0;
+        self.latch1 = x.latch1;
+        self.horizontalmirroring = x.horizontalmirroring;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        match location {
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
+            //MMC2 switches 8 KiB at $8000 and fixes the last three; MMC4 switches 16 KiB and fixes the last
+            0x8000...0x9FFF if !self.mmc4 => self.ines.prgrom[(0x2000*self.prgbank + location - 0x8000) % len],
+            0x8000...0xBFFF if self.mmc4 => self.ines.prgrom[(0x4000*self.prgbank + location - 0x8000) % len],
+            //the fixed banks count back from the end of PRG-ROM, which repeats if it's smaller than them
+            0x8000...0xFFFF => self.ines.prgrom[(len - (0x10000 - location) % len) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        let what = what as usize;
+        match location {
+            0x6000...0x7FFF => prgram_write(&mut self.prgram,location as usize - 0x6000,what as u8),
+            0xA000...0xAFFF => self.prgbank = what & 0xF,
+            0xB000...0xBFFF => self.chrbanks[0] = what & 0x1F,
+            0xC000...0xCFFF => self.chrbanks[1] = what & 0x1F,
+            0xD000...0xDFFF => self.chrbanks[2] = what & 0x1F,
+            0xE000...0xEFFF => self.chrbanks[3] = what & 0x1F,
+            0xF000...0xFFFF => self.horizontalmirroring = what & 1 != 0,
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.update_latches(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//boards built around the MMC3 or the Namco 108 it grew out of, which has only the bank registers
+#[derive(Clone,Copy,PartialEq)]
+enum Mmc3Board {
+    MMC3,
+    MMC6, //1 KiB of PRG-RAM inside the chip, with its own protection
+    TxSROM, //118: CHR bank bit 7 picks the nametable
+    TQROM, //119: CHR bank bit 6 picks 8 KiB of CHR-RAM
+    Namco108, //206
+    Namco3433, //88: $1000-$1FFF fetches from the second 64 KiB of CHR
+    Namco3425, //95: CHR bank bit 5 picks the nametable
+    Namco3453 //154: 88 plus one screen mirroring
+}
+
+pub struct MMC3 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    board: Mmc3Board,
+    tqrom_chrram: Vec<u8>,
+    inputselect: u8,
+    prgrommode: bool,
+    chrrommode: bool,
+    chrbank0: usize,
+    chrbank1: usize,
+    chrbank2: usize,
+    chrbank3: usize,
+    chrbank4: usize,
+    chrbank5: usize,
+    prgbank0: usize,
+    prgbank1: usize,
+    horizontalmirroring: bool,
+    upper_screen: bool, //Namco 3453
+    prgram_enabled: bool, //MMC6 $8000 bit 5
+    prgram_protect: u8, //MMC6 $A001
+    irqreload: u8,
+    irqcounter: u8,
+    reload_pending: bool, //$C001 reloads on the next clock
+    rev_a: bool,
+    a12: bool,
+    a12_low_cycles: u8,
+    generate_irq: bool,
+    interrupt: bool
+}
+#[derive(Serialize,Deserialize)]
+struct MMC3_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    tqrom_chrram: Vec<u8>,
+    inputselect: u8,
+    prgrommode: bool,
+    chrrommode: bool,
+    chrbank0: usize,
+    chrbank1: usize,
+    chrbank2: usize,
+    chrbank3: usize,
+    chrbank4: usize,
+    chrbank5: usize,
+    prgbank0: usize,
+    prgbank1: usize,
+    horizontalmirroring: bool,
+    upper_screen: bool,
+    prgram_enabled: bool,
+    prgram_protect: u8,
+    irqreload: u8,
+    irqcounter: u8,
+    reload_pending: bool,
+    a12: bool,
+    a12_low_cycles: u8,
+    generate_irq: bool,
+    interrupt: bool
+}
+
+const MMC3_A12_FILTER: u8 = 3;
+
+impl MMC3 {
+    fn new(ines:INES,prgram:Vec<u8>) -> MMC3 {
+        let board = match (ines.mapper,ines.submapper) {
+            (4,1) => Mmc3Board::MMC6,
+            (88,_) => Mmc3Board::Namco3433,
+            (95,_) => Mmc3Board::Namco3425,
+            (118,_) => Mmc3Board::TxSROM,
+            (119,_) => Mmc3Board::TQROM,
+            (154,_) => Mmc3Board::Namco3453,
+            (206,_) => Mmc3Board::Namco108,
+            (_,_) => Mmc3Board::MMC3
+        };
+        let rev_a = ines.mapper == 4 && ines.submapper == 4;
+        MMC3 {
+            ines: ines,
+            prgram: prgram,
+            nametables:[[0;0x400];4],
+            board: board,
+            tqrom_chrram: if board == Mmc3Board::TQROM { vec![0;0x2000] } else { vec![] },
+            inputselect: 0,
+            prgrommode: false,
+            chrrommode: false,
+            chrbank0: 0,
+            chrbank1: 0,
+            chrbank2: 0,
+            chrbank3: 0,
+            chrbank4: 0,
+            chrbank5: 0,
+            prgbank0: 0,
+            prgbank1: 0,
+            horizontalmirroring: false,
+            upper_screen: false,
+            prgram_enabled: false,
+            prgram_protect: 0,
+            irqreload: 0,
+            irqcounter: 0,
+            reload_pending: false,
+            rev_a: rev_a,
+            a12: false,
+            a12_low_cycles: 0,
+            generate_irq: false,
+            interrupt: false,
+        }
+    }
+    fn namco(&self) -> bool {
+        match self.board {
+            Mmc3Board::Namco108 | Mmc3Board::Namco3433 | Mmc3Board::Namco3425 | Mmc3Board::Namco3453 => true,
+            _ => false
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.board {
+            Mmc3Board::Namco108 | Mmc3Board::Namco3433 => header_mirroring(&self.ines),
+            Mmc3Board::Namco3453 if self.upper_screen => Mirroring::SingleScreenUpper,
+            Mmc3Board::Namco3453 => Mirroring::SingleScreenLower,
+            _ if self.ines.four_screen => Mirroring::FourScreen,
+            _ if self.horizontalmirroring => Mirroring::Horizontal,
+            _ => Mirroring::Vertical
+        }
+    }
+    //TxSROM and Namco 3425 wire a CHR bank bit to CIRAM A10, so each nametable follows the
+    //bank register for the matching 1 KiB of $0000-$0FFF
+    fn nametable(&self,location:usize) -> usize {
+        let bank = self.chr_bank((location >> 10 & 3) << 10);
+        match self.board {
+            Mmc3Board::TxSROM => (bank >> 7) & 1,
+            Mmc3Board::Namco3425 => (bank >> 5) & 1,
+            _ => nametable(location,self.mirroring())
+        }
+    }
+    //the 1 KiB bank number the registers give a pattern table address
+    fn chr_bank(&self,location:usize) -> usize {
+        let location = if self.chrrommode { location ^ 0x1000 } else { location };
+        match location >> 10 {
+            0 => self.chrbank0 << 1,
+            1 => self.chrbank0 << 1 | 1,
+            2 => self.chrbank1 << 1,
+            3 => self.chrbank1 << 1 | 1,
+            4 => self.chrbank2,
+            5 => self.chrbank3,
+            6 => self.chrbank4,
+            _ => self.chrbank5
+        }
+    }
+    //where a pattern table address lands: true for TQROM's CHR-RAM
+    fn chr_index(&self,location:usize) -> (bool,usize) {
+        let bank = self.chr_bank(location);
+        let bank = match self.board {
+            Mmc3Board::TQROM if bank & 0x40 != 0 => return (true,0x400*(bank & 7) + (location & 0x3FF)),
+            Mmc3Board::Namco3433 | Mmc3Board::Namco3453 if location >= 0x1000 => bank | 0x40,
+            Mmc3Board::Namco3433 | Mmc3Board::Namco3453 => bank & 0x3F,
+            _ => bank
+        };
+        (false,(0x400*bank + (location & 0x3FF)) % self.ines.chrrom.len())
+    }
+    //MMC6 RAM is 1 KiB at $7000-$7FFF in two halves, each with its own read and write enable
+    fn mmc6_readable(&self,location:usize) -> bool {
+        let half = if location & 0x200 != 0 { 0x80 } else { 0x20 };
+        self.prgram_enabled && self.prgram_protect & half != 0
+    }
+    fn mmc6_writable(&self,location:usize) -> bool {
+        let half = if location & 0x200 != 0 { 0xC0 } else { 0x30 };
+        self.prgram_enabled && self.prgram_protect & half == half
+    }
+    //Rev B and later fire whenever the counter is 0 after a clock, so a latch of 0 fires every
+    //line; Rev A (submapper 4) only fires counting down to 0 or on the reload after $C001
+    fn clock_irq(&mut self) {
+        let count = self.irqcounter;
+        if self.irqcounter == 0 || self.reload_pending {
+            self.irqcounter = self.irqreload;
+        } else {
+            self.irqcounter -= 1;
+        }
+        if self.irqcounter == 0 && self.generate_irq && (!self.rev_a || count != 0 || self.reload_pending) {
+            self.interrupt = true;
+        }
+        self.reload_pending = false;
+    }
+}
+
+impl Mapper for MMC3 {
+    fn serialize(&self) -> Vec<u8> {
+        let mut vprgram = vec![];
+        vprgram.extend_from_slice(&self.prgram);
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = MMC3_Serial {
+            prgram: vprgram,
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            tqrom_chrram: self.tqrom_chrram.clone(),
+            inputselect: self.inputselect,
+            prgrommode: self.prgrommode,
+            chrrommode: self.chrrommode,
+            chrbank0: self.chrbank0,
+            chrbank1: self.chrbank1,
+            chrbank2: self.chrbank2,
+            chrbank3: self.chrbank3,
+            chrbank4: self.chrbank4,
+            chrbank5: self.chrbank5,
+            prgbank0: self.prgbank0,
+            prgbank1: self.prgbank1,
+            horizontalmirroring: self.horizontalmirroring,
+            upper_screen: self.upper_screen,
+            prgram_enabled: self.prgram_enabled,
+            prgram_protect: self.prgram_protect,
+            irqreload: self.irqreload,
+            irqcounter: self.irqcounter,
+            reload_pending: self.reload_pending,
+            a12: self.a12,
+            a12_low_cycles: self.a12_low_cycles,
+            generate_irq: self.generate_irq,
+            interrupt: self.interrupt
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: MMC3_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.tqrom_chrram.copy_from_slice(&x.tqrom_chrram);
+        self.inputselect = x.inputselect;
+        self.prgrommode = x.prgrommode;
+        self.chrrommode = x.chrrommode;
+        self.chrbank0 = x.chrbank0;
+        self.chrbank1 = x.chrbank1;
+        self.chrbank2 = x.chrbank2;
+        self.chrbank3 = x.chrbank3;
+        self.chrbank4 = x.chrbank4;
+        self.chrbank5 = x.chrbank5;
+        self.prgbank0 = x.prgbank0;
+        self.prgbank1 = x.prgbank1;
+        self.horizontalmirroring = x.horizontalmirroring;
+        self.upper_screen = x.upper_screen;
+        self.prgram_enabled = x.prgram_enabled;
+        self.prgram_protect = x.prgram_protect;
+        self.irqreload = x.irqreload;
+        self.irqcounter = x.irqcounter;
+        self.reload_pending = x.reload_pending;
+        self.a12 = x.a12;
+        self.a12_low_cycles = x.a12_low_cycles;
+        self.generate_irq = x.generate_irq;
+        self.interrupt = x.interrupt;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let prgrom_size = self.ines.prgrom_size as usize * 2;
+        let prgbank0 = self.prgbank0 % prgrom_size;
+        let prgbank1 = self.prgbank1 % prgrom_size;
+        match location {
+            0x6000...0x6FFF if self.board == Mmc3Board::MMC6 => 0,
+            0x7000...0x7FFF if self.board == Mmc3Board::MMC6 => {
+                if self.mmc6_readable(location) {
+                    prgram_read(&self.prgram,location & 0x3FF)
+                } else {
+                    0
+                }
+            }
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
+            0x8000...0x9FFF => {
+                if self.prgrommode {
+                    self.ines.prgrom[0x2000*(prgrom_size - 2) + location - 0x8000]
+                } else {
+                    self.ines.prgrom[0x2000*prgbank0 + location - 0x8000]
+                }
+            }
+            0xA000...0xBFFF => self.ines.prgrom[0x2000*prgbank1 + location - 0xA000],
+            0xC000...0xDFFF => {
+                if !self.prgrommode {
+                    self.ines.prgrom[0x2000*(prgrom_size - 2) + location - 0xC000]
+                } else {
+                    self.ines.prgrom[0x2000*prgbank0 + location - 0xC000]
+                }
+            }
+            0xE000...0xFFFF => self.ines.prgrom[0x2000*(prgrom_size - 1) + location - 0xE000],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        if self.board == Mmc3Board::Namco3453 && location >= 0x8000 {
+            self.upper_screen = what & 0x40 != 0;
+        }
+        match location {
+            0x6000...0x7FFF if self.board == Mmc3Board::MMC6 => {
+                if location >= 0x7000 && self.mmc6_writable(location as usize) {
+                    prgram_write(&mut self.prgram,location as usize & 0x3FF,what);
+                }
+            }
+            0x6000...0x7FFF => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
+            //the Namco 108 has no mode bits, and nothing past $9FFF
+            0x8000...0x9FFF if self.namco() && location & 1 == 0 => self.inputselect = what & 0x7,
+            0xA000...0xFFFF if self.namco() => (),
+            0x8000...0x9FFF => {
+                if location & 1 == 0 {
+                    self.prgram_enabled = what & 0x20 != 0;
+                    self.inputselect = what & 0x7;
+                    self.prgrommode = what & 0x40 != 0;
+                    self.chrrommode = what & 0x80 != 0;
+                } else {
+                    let what = what as usize;
+                    match self.inputselect {
+                        0 => self.chrbank0 = what >> 1,
+                        1 => self.chrbank1 = what >> 1,
+                        2 => self.chrbank2 = what,
+                        3 => self.chrbank3 = what,
+                        4 => self.chrbank4 = what,
+                        5 => self.chrbank5 = what,
+                        6 => self.prgbank0 = what & 0x3F,
+                        7 => self.prgbank1 = what & 0x3F,
+                        _ => ()
+                    }
+                }
+            }
+            0xA000...0xBFFF => {
+                if location & 1 == 0 {
+                    self.horizontalmirroring = what & 1 != 0;
+                } else if self.board == Mmc3Board::MMC6 && self.prgram_enabled {
+                    self.prgram_protect = what;
+                } else {
+                    () //not neccesary to emulate; ram write protection
+                }
+            }
+            0xC000...0xDFFF => {
+                if location & 1 == 0 {
+                    self.irqreload = what;
+                } else {
+                    self.irqcounter = 0;
+                    self.reload_pending = true;
+                }
+            }
+            0xE000...0xFFFF => {
+                if location & 1 == 0 {
+                    //disabling also acknowledges
+                    self.generate_irq = false;
+                    self.interrupt = false;
+                } else {
+                    self.generate_irq = true;
+                }
+            }
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                match self.chr_index(location) {
+                    (true,index) => self.tqrom_chrram[index],
+                    (false,index) => self.ines.chrrom[index]
+                }
+            }
+            0x2000...0x3EFF => {
+                let nametable = self.nametable(location);
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                match self.chr_index(location) {
+                    (true,index) => self.tqrom_chrram[index] = what,
+                    (false,_) if self.board == Mmc3Board::TQROM => (),
+                    (false,index) => self.ines.chrrom[index] = what
+                }
+            }
+            0x2000...0x3EFF => {
+                let nametable = self.nametable(location);
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.interrupt
+    }
+    //A12 only counts as rising after it has been low for a few CPU cycles, which filters out
+    //the short dips for nametable fetches between pattern fetches from $1000
+    fn ppu_address(&mut self,address:u16) {
+        let a12 = address & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low_cycles >= MMC3_A12_FILTER {
+            self.clock_irq();
+        }
+        if !a12 && self.a12 {
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+    fn cpu_cycle(&mut self) {
+        if !self.a12 && self.a12_low_cycles < MMC3_A12_FILTER {
+            self.a12_low_cycles += 1;
+        }
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//MMC5 (ExROM)
+//https://wiki.nesdev.com/w/index.php/MMC5
+struct MMC5 {
+    ines: INES,
+    prgram: Vec<u8>,
+    exram: Vec<u8>,
+    nametables:[[u8;0x400];2],
+    prgmode: u8,
+    chrmode: u8,
+    ramprotect1: u8,
+    ramprotect2: u8,
+    exrammode: u8,
+    nametablemapping: u8,
+    filltile: u8,
+    fillcolor: u8,
+    prgbanks: [u8;5], //$5113-$5117
+    chrbanks_a: [usize;8], //$5120-$5127, with the upper bits from $5130 when written
+    chrbanks_b: [usize;4], //$5128-$512B, for the background with 8x16 sprites
+    chrupper: usize,
+    last_chr_b: bool,
+    splitcontrol: u8,
+    splitscroll: u8,
+    splitbank: usize,
+    irqcompare: u8,
+    irqenabled: bool,
+    irqpending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    //what the MMC5 has worked out from watching the PPU
+    in_frame: bool,
+    scanline_counter: u8,
+    idle_cycles: u32,
+    sprite_fetch: bool,
+    big_sprites: bool,
+    tile: usize,
+    split_tile: bool,
+    split_y: u8,
+    exattribute: u8,
+    audio: Mmc5Audio
+}
+
+#[derive(Serialize,Deserialize)]
+struct MMC5_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    exram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgmode: u8,
+    chrmode: u8,
+    ramprotect1: u8,
+    ramprotect2: u8,
+    exrammode: u8,
+    nametablemapping: u8,
+    filltile: u8,
+    fillcolor: u8,
+    prgbanks: [u8;5],
+    chrbanks_a: [usize;8],
+    chrbanks_b: [usize;4],
+    chrupper: usize,
+    last_chr_b: bool,
+    splitcontrol: u8,
+    splitscroll: u8,
+    splitbank: usize,
+    irqcompare: u8,
+    irqenabled: bool,
+    irqpending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    in_frame: bool,
+    scanline_counter: u8,
+    idle_cycles: u32,
+    sprite_fetch: bool,
+    big_sprites: bool,
+    tile: usize,
+    split_tile: bool,
+    split_y: u8,
+    exattribute: u8,
+    audio: Mmc5Audio
+}
+
+//CPU cycles without PPU fetches before the MMC5 decides rendering has stopped
+const MMC5_IDLE_CYCLES: u32 = 120;
+
+impl MMC5 {
+    fn new(ines:INES,prgram:Vec<u8>) -> MMC5 {
+        MMC5 {
+            ines: ines,
+            prgram: prgram,
+            exram: vec![0;0x400],
+            nametables: [[0;0x400];2],
+            prgmode: 3,
+            chrmode: 0,
+            ramprotect1: 0,
+            ramprotect2: 0,
+            exrammode: 0,
+            nametablemapping: 0,
+            filltile: 0,
+            fillcolor: 0,
+            prgbanks: [0,0,0,0,0xFF],
+            chrbanks_a: [0;8],
+            chrbanks_b: [0;4],
+            chrupper: 0,
+            last_chr_b: false,
+            splitcontrol: 0,
+            splitscroll: 0,
+            splitbank: 0,
+            irqcompare: 0,
+            irqenabled: false,
+            irqpending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            in_frame: false,
+            scanline_counter: 0,
+            idle_cycles: 0,
+            sprite_fetch: false,
+            big_sprites: false,
+            tile: 0,
+            split_tile: false,
+            split_y: 0,
+            exattribute: 0,
+            audio: Mmc5Audio::new()
+        }
+    }
+    //whether $6000-$FFFF is ROM, and the 8 KiB bank there
+    fn prg_bank(&self,location:usize) -> (bool,usize) {
+        let slot = (location - 0x6000) >> 13;
+        if slot == 0 {
+            return (false,self.prgbanks[0] as usize & 7);
+        }
+        //register in prgbanks and bank size in 8 KiB units
+        let (register,size) = match (self.prgmode,slot) {
+            (0,_) => (4,4),
+            (1,1...2) | (2,1...2) => (2,2),
+            (1,_) => (4,2),
+            (2,3) => (3,1),
+            (2,_) => (4,1),
+            (_,slot) => (slot,1)
+        };
+        let value = self.prgbanks[register] as usize;
+        let rom = register == 4 || value & 0x80 != 0;
+        (rom,(value & 0x7F & !(size - 1)) | ((slot - 1) & (size - 1)))
+    }
+    //two 8 KiB chips take bank bit 2 as the chip select
+    fn prgram_offset(&self,bank:usize,location:usize) -> usize {
+        let bank = if self.prgram.len() == 0x4000 { bank >> 2 & 1 } else { bank & 7 };
+        0x2000*bank + (location & 0x1FFF)
+    }
+    fn prgram_writable(&self) -> bool {
+        self.ramprotect1 == 2 && self.ramprotect2 == 1
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        let use_b = self.big_sprites && if self.in_frame { !self.sprite_fetch } else { self.last_chr_b };
+        let size = 0x2000 >> self.chrmode;
+        let register = ((location / size + 1) << (3 - self.chrmode)) - 1;
+        let bank = if use_b { self.chrbanks_b[register & 3] } else { self.chrbanks_a[register] };
+        (size*bank + location % size) % self.ines.chrrom.len()
+    }
+    //background fetches on a rendered line, where the split and extended attributes apply
+    fn background_fetch(&self) -> bool {
+        self.in_frame && !self.sprite_fetch
+    }
+    //a nametable fetch starts the next tile
+    fn next_tile(&mut self) {
+        let column = self.tile as u8;
+        self.tile += 1;
+        let threshold = self.splitcontrol & 0x1F;
+        self.split_tile = self.splitcontrol & 0x80 != 0 && self.exrammode < 2 && if self.splitcontrol & 0x40 == 0 {
+            column < threshold
+        } else {
+            column >= threshold
+        };
+    }
+    fn split_nametable(&self,location:usize) -> u8 {
+        let column = (self.tile - 1) & 0x1F;
+        let row = self.split_y as usize >> 3;
+        if location & 0x3FF < 0x3C0 {
+            self.exram[(row << 5 | column) & 0x3FF]
+        } else {
+            let attribute = self.exram[0x3C0 + ((row >> 2) << 3 | column >> 2) & 0x3FF];
+            let shift = (row & 2) << 1 | column & 2;
+            ((attribute >> shift) & 3) * 0x55
+        }
+    }
+    fn nametable_read(&self,location:usize) -> u8 {
+        let offset = location & 0x3FF;
+        match (self.nametablemapping >> ((location >> 9) & 6)) & 3 {
+            0 => self.nametables[0][offset],
+            1 => self.nametables[1][offset],
+            2 => if self.exrammode < 2 { self.exram[offset] } else { 0 },
+            _ => if offset < 0x3C0 { self.filltile } else { self.fillcolor * 0x55 }
+        }
+    }
+    fn nametable_write(&mut self,location:usize,what:u8) {
+        let offset = location & 0x3FF;
+        match (self.nametablemapping >> ((location >> 9) & 6)) & 3 {
+            0 => self.nametables[0][offset] = what,
+            1 => self.nametables[1][offset] = what,
+            2 if self.exrammode < 2 => self.exram[offset] = what,
+            _ => ()
+        }
+    }
+}
+
+impl Mapper for MMC5 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = MMC5_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            exram: self.exram.clone(),
+            nametables: vnametables,
+            prgmode: self.prgmode,
+            chrmode: self.chrmode,
+            ramprotect1: self.ramprotect1,
+            ramprotect2: self.ramprotect2,
+            exrammode: self.exrammode,
+            nametablemapping: self.nametablemapping,
+            filltile: self.filltile,
+            fillcolor: self.fillcolor,
+            prgbanks: self.prgbanks,
+            chrbanks_a: self.chrbanks_a,
+            chrbanks_b: self.chrbanks_b,
+            chrupper: self.chrupper,
+            last_chr_b: self.last_chr_b,
+            splitcontrol: self.splitcontrol,
+            splitscroll: self.splitscroll,
+            splitbank: self.splitbank,
+            irqcompare: self.irqcompare,
+            irqenabled: self.irqenabled,
+            irqpending: self.irqpending,
+            multiplicand: self.multiplicand,
+            multiplier: self.multiplier,
+            in_frame: self.in_frame,
+            scanline_counter: self.scanline_counter,
+            idle_cycles: self.idle_cycles,
+            sprite_fetch: self.sprite_fetch,
+            big_sprites: self.big_sprites,
+            tile: self.tile,
+            split_tile: self.split_tile,
+            split_y: self.split_y,
+            exattribute: self.exattribute,
+            audio: self.audio.clone()
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: MMC5_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        self.exram.copy_from_slice(&x.exram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgmode = x.prgmode;
+        self.chrmode = x.chrmode;
+        self.ramprotect1 = x.ramprotect1;
+        self.ramprotect2 = x.ramprotect2;
+        self.exrammode = x.exrammode;
+        self.nametablemapping = x.nametablemapping;
+        self.filltile = x.filltile;
+        self.fillcolor = x.fillcolor;
+        self.prgbanks = x.prgbanks;
+        self.chrbanks_a = x.chrbanks_a;
+        self.chrbanks_b = x.chrbanks_b;
+        self.chrupper = x.chrupper;
+        self.last_chr_b = x.last_chr_b;
+        self.splitcontrol = x.splitcontrol;
+        self.splitscroll = x.splitscroll;
+        self.splitbank = x.splitbank;
+        self.irqcompare = x.irqcompare;
+        self.irqenabled = x.irqenabled;
+        self.irqpending = x.irqpending;
+        self.multiplicand = x.multiplicand;
+        self.multiplier = x.multiplier;
+        self.in_frame = x.in_frame;
+        self.scanline_counter = x.scanline_counter;
+        self.idle_cycles = x.idle_cycles;
+        self.sprite_fetch = x.sprite_fetch;
+        self.big_sprites = x.big_sprites;
+        self.tile = x.tile;
+        self.split_tile = x.split_tile;
+        self.split_y = x.split_y;
+        self.exattribute = x.exattribute;
+        self.audio = x.audio;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        match location {
+            0x5010 | 0x5015 => self.audio.read(location),
+            0x5204 => {
+                let result = (self.irqpending as u8) << 7 | (self.in_frame as u8) << 6;
+                self.irqpending = false;
+                result
+            }
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00...0x5FFF if self.exrammode >= 2 => self.exram[location as usize - 0x5C00],
+            0x6000...0xFFFF => {
+                let location = location as usize;
+                let (rom,bank) = self.prg_bank(location);
+                if rom {
+                    let len = self.ines.prgrom.len();
+                    let result = self.ines.prgrom[(0x2000*bank + (location & 0x1FFF)) % len];
+                    if location < 0xC000 {
+                        self.audio.pcm_read(result);
+                    }
+                    result
+                } else {
+                    prgram_read(&self.prgram,self.prgram_offset(bank,location))
+                }
+            }
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x5000...0x5015 => self.audio.write(location,what),
+            0x5100 => self.prgmode = what & 3,
+            0x5101 => self.chrmode = what & 3,
+            0x5102 => self.ramprotect1 = what & 3,
+            0x5103 => self.ramprotect2 = what & 3,
+            0x5104 => self.exrammode = what & 3,
+            0x5105 => self.nametablemapping = what,
+            0x5106 => self.filltile = what,
+            0x5107 => self.fillcolor = what & 3,
+            0x5113...0x5117 => self.prgbanks[location as usize - 0x5113] = what,
+            0x5120...0x5127 => {
+                self.chrbanks_a[location as usize - 0x5120] = what as usize | self.chrupper << 8;
+                self.last_chr_b = false;
+            }
+            0x5128...0x512B => {
+                self.chrbanks_b[location as usize - 0x5128] = what as usize | self.chrupper << 8;
+                self.last_chr_b = true;
+            }
+            0x5130 => self.chrupper = what as usize & 3,
+            0x5200 => self.splitcontrol = what,
+            0x5201 => self.splitscroll = what,
+            0x5202 => self.splitbank = what as usize,
+            0x5203 => self.irqcompare = what,
+            0x5204 => self.irqenabled = what & 0x80 != 0,
+            0x5205 => self.multiplicand = what,
+            0x5206 => self.multiplier = what,
+            //as a nametable, ExRAM can only be written while rendering
+            0x5C00...0x5FFF => match self.exrammode {
+                0...1 => self.exram[location as usize - 0x5C00] = if self.in_frame { what } else { 0 },
+                2 => self.exram[location as usize - 0x5C00] = what,
+                _ => ()
+            },
+            0x6000...0xFFFF if self.prgram_writable() => {
+                let location = location as usize;
+                let (rom,bank) = self.prg_bank(location);
+                if !rom {
+                    let offset = self.prgram_offset(bank,location);
+                    prgram_write(&mut self.prgram,offset,what);
+                }
+            }
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = if self.background_fetch() && self.split_tile {
+                    //the split has its own 4 KiB bank and fine y
+                    0x1000*self.splitbank + (location & 0xFF8) + (self.split_y as usize & 7)
+                } else if self.background_fetch() && self.exrammode == 1 {
+                    0x1000*((self.exattribute as usize & 0x3F) | self.chrupper << 6) + (location & 0xFFF)
+                } else {
+                    self.chr_index(location)
+                };
+                self.ines.chrrom[index % self.ines.chrrom.len()]
+            }
+            0x2000...0x3EFF => {
+                let attribute = location & 0x3FF >= 0x3C0;
+                if self.background_fetch() {
+                    if !attribute {
+                        self.next_tile();
+                    }
+                    if self.split_tile {
+                        return self.split_nametable(location);
+                    }
+                    if self.exrammode == 1 {
+                        //extended attributes: ExRAM picks each tile's palette and 4 KiB CHR bank
+                        if attribute {
+                            return (self.exattribute >> 6) * 0x55;
+                        }
+                        self.exattribute = self.exram[location & 0x3FF];
+                    }
+                }
+                self.nametable_read(location)
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => self.nametable_write(location,what),
+            _ => ()
+        }
+    }
+    fn cpu_cycle(&mut self) {
+        self.audio.cycle();
+        if self.in_frame {
+            self.idle_cycles += 1;
+            if self.idle_cycles > MMC5_IDLE_CYCLES {
+                self.in_frame = false;
+                self.sprite_fetch = false;
+            }
+        }
+    }
+    //the real chip spots new scanlines from repeated nametable fetches; the PPU just says so
+    fn ppu_fetch(&mut self,scanline:u16,sprites:bool,big_sprites:bool) {
+        self.sprite_fetch = sprites;
+        self.big_sprites = big_sprites;
+        self.idle_cycles = 0;
+        if sprites {
+            return;
+        }
+        self.tile = 0;
+        self.split_tile = false;
+        if scanline == 239 {
+            //the frame is over once the last visible line is done
+            self.in_frame = false;
+            self.irqpending = false;
+        } else if scanline == 261 || !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            self.split_y = self.splitscroll;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            self.split_y = if self.split_y == 239 { 0 } else { self.split_y.wrapping_add(1) };
+            if self.scanline_counter == self.irqcompare {
+                self.irqpending = true;
+            }
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.irqpending && self.irqenabled || self.audio.interrupt()
+    }
+    fn audio_output(&self) -> i32 {
+        self.audio.output()
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//the IRQ counter shared by Konami's VRC4, VRC6 and VRC7: an 8-bit up counter clocked each
+//scanline (a CPU cycle prescaler counting 341 PPU dots) or, in cycle mode, each CPU cycle
+//https://wiki.nesdev.com/w/index.php/VRC_IRQ
+#[derive(Serialize,Deserialize,Clone)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i32,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool
+}
+
+impl VrcIrq {
+    fn new() -> VrcIrq {
+        VrcIrq {latch: 0,counter: 0,prescaler: 341,enabled: false,enable_after_ack: false,cycle_mode: false,pending: false}
+    }
+    fn write_control(&mut self,what:u8) {
+        self.enable_after_ack = what & 1 != 0;
+        self.enabled = what & 2 != 0;
+        self.cycle_mode = what & 4 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+    fn cpu_cycle(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock();
+            }
+        }
+    }
+    fn clock(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+//Konami VRC2 and VRC4 (21, 22, 23, 25)
+//https://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+//the boards differ in which CPU address lines pick the register within each $x000 block; the
+//submapper says which, otherwise both candidate lines are watched, which suits nearly every game
+//mappers 23 and 25 without a submapper could be either chip, so they start out as VRC2 and turn
+//into VRC4 the first time the game touches the IRQ registers, which VRC2 doesn't have
+pub struct VRC24 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    pub vrc2: bool, //for testing purposes
+    vrc4_detect: bool,
+    pub register_lines: (u16,u16), //address lines for register bit 0 and bit 1, public for testing purposes
+    chr_shift: usize, //VRC2a ignores the low bit of its CHR banks
+    prgbanks: [usize;2],
+    prgswap: bool,
+    chrbanks: [usize;8],
+    mirroring: u8,
+    latch: u8, //VRC2 carts without PRG-RAM have one bit at $6000
+    irq: VrcIrq
+}
+
+#[derive(Serialize,Deserialize)]
+struct VRC24_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbanks: [usize;2],
+    prgswap: bool,
+    chrbanks: [usize;8],
+    mirroring: u8,
+    latch: u8,
+    irq: VrcIrq,
+    vrc2: bool,
+    vrc4_detect: bool
+}
+
+impl VRC24 {
+    pub fn new(ines:INES,prgram:Vec<u8>) -> VRC24 {
+        let (register_lines,vrc2) = match (ines.mapper,ines.submapper) {
+            (21,1) => ((0x02,0x04),false), //VRC4a
+            (21,2) => ((0x40,0x80),false), //VRC4c
+            (21,_) => ((0x42,0x84),false),
+            (22,_) => ((0x02,0x01),true), //VRC2a
+            (23,1) => ((0x01,0x02),false), //VRC4f
+            (23,2) => ((0x04,0x08),false), //VRC4e
+            (23,3) => ((0x01,0x02),true), //VRC2b
+            (23,_) => ((0x05,0x0A),true),
+            (25,1) => ((0x02,0x01),false), //VRC4b
+            (25,2) => ((0x08,0x04),false), //VRC4d
+            (25,3) => ((0x02,0x01),true), //VRC2c
+            (_,_) => ((0x0A,0x05),true)
+        };
+        let vrc4_detect = (ines.mapper == 23 || ines.mapper == 25) && ines.submapper == 0;
+        VRC24 {
+            chr_shift: if ines.mapper == 22 { 1 } else { 0 },
+            ines: ines,
+            prgram: prgram,
+            nametables: [[0;0x400];4],
+            vrc2: vrc2,
+            vrc4_detect: vrc4_detect,
+            register_lines: register_lines,
+            prgbanks: [0;2],
+            prgswap: false,
+            chrbanks: [0;8],
+            mirroring: 0,
+            latch: 0,
+            irq: VrcIrq::new()
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring & if self.vrc2 { 1 } else { 3 } {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        //VRC2 only has four high bits per bank
+        let bank = (self.chrbanks[location >> 10] & if self.vrc2 { 0xFF } else { 0x1FF }) >> self.chr_shift;
+        (0x400*bank + (location & 0x3FF)) % self.ines.chrrom.len()
+    }
+    //$x000-$x003, whichever lines the board wires up
+    fn register(&self,location:u16) -> u16 {
+        let (bit0,bit1) = self.register_lines;
+        location & 0xF000 | (location & bit0 != 0) as u16 | ((location & bit1 != 0) as u16) << 1
+    }
+}
+
+impl Mapper for VRC24 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = VRC24_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbanks: self.prgbanks,
+            prgswap: self.prgswap,
+            chrbanks: self.chrbanks,
+            mirroring: self.mirroring,
+            latch: self.latch,
+            irq: self.irq.clone(),
+            vrc2: self.vrc2,
+            vrc4_detect: self.vrc4_detect
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: VRC24_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbanks = x.prgbanks;
+        self.prgswap = x.prgswap;
+        self.chrbanks = x.chrbanks;
+        self.mirroring = x.mirroring;
+        self.latch = x.latch;
+        self.irq = x.irq;
+        self.vrc2 = x.vrc2;
+        self.vrc4_detect = x.vrc4_detect;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        //the swap bit trades $8000 with the fixed second to last bank at $C000
+        let (bank8000,bankc000) = if self.prgswap {
+            (len / 0x2000 - 2,self.prgbanks[0])
+        } else {
+            (self.prgbanks[0],len / 0x2000 - 2)
+        };
+        match location {
+            0x6000...0x6FFF if self.vrc2 && self.prgram.len() == 0 => self.latch,
+            0x6000...0x7FFF => prgram_read(&self.prgram,location - 0x6000),
+            0x8000...0x9FFF => self.ines.prgrom[(0x2000*bank8000 + location - 0x8000) % len],
+            0xA000...0xBFFF => self.ines.prgrom[(0x2000*self.prgbanks[1] + location - 0xA000) % len],
+            0xC000...0xDFFF => self.ines.prgrom[(0x2000*bankc000 + location - 0xC000) % len],
+            0xE000...0xFFFF => self.ines.prgrom[(len - 0x2000 + (location & 0x1FFF)) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        if location < 0x8000 {
+            match location {
+                0x6000...0x6FFF if self.vrc2 && self.prgram.len() == 0 => self.latch = what & 1,
+                0x6000...0x7FFF => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
+                _ => ()
+            }
+            return;
+        }
+        let register = self.register(location);
+        let what = what as usize;
+        match register {
+            0x8000...0x8003 => self.prgbanks[0] = what & 0x1F,
+            0x9000...0x9003 if self.vrc2 => self.mirroring = what as u8,
+            0x9000 => self.mirroring = what as u8,
+            //bit 0 is a PRG-RAM enable, which the RAM is left on regardless of
+            0x9002 => self.prgswap = what & 2 != 0,
+            0xA000...0xA003 => self.prgbanks[1] = what & 0x1F,
+            0xB000...0xE003 => {
+                //four low bits then the high bits of each of the eight 1 KiB banks
+                let bank = ((register as usize >> 12) - 0xB) * 2 + (register as usize >> 1 & 1);
+                self.chrbanks[bank] = if register & 1 == 0 {
+                    self.chrbanks[bank] & !0xF | what & 0xF
+                } else {
+                    self.chrbanks[bank] & 0xF | (what & 0x1F) << 4
+                };
+            }
+            0xF000...0xF003 if self.vrc4_detect => {
+                self.vrc2 = false;
+                self.vrc4_detect = false;
+                self.set_contents(location,what as u8);
+            }
+            0xF000 if !self.vrc2 => self.irq.latch = self.irq.latch & 0xF0 | what as u8 & 0xF,
+            0xF001 if !self.vrc2 => self.irq.latch = self.irq.latch & 0xF | (what as u8) << 4,
+            0xF002 if !self.vrc2 => self.irq.write_control(what as u8),
+            0xF003 if !self.vrc2 => self.irq.acknowledge(),
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+    fn interrupt(&mut self) -> bool {
+        self.irq.pending
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//Konami VRC6 (24, and 26 with A0 and A1 swapped)
+//the sound registers are passed on to the blargg library's VRC6 through chip_writes
+struct VRC6 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    swapped_lines: bool,
+    prgbank16: usize,
+    prgbank8: usize,
+    chrbanks: [usize;8],
+    control: u8, //$B003
+    irq: VrcIrq,
+    chip_writes: Vec<ChipWrite>
+}
+
+#[derive(Serialize,Deserialize)]
+struct VRC6_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbank16: usize,
+    prgbank8: usize,
+    chrbanks: [usize;8],
+    control: u8,
+    irq: VrcIrq
+}
+
+impl VRC6 {
+    //only the 1 KiB CHR banking and CIRAM nametables that every released game uses; the
+    //other $B003 modes that map CHR-ROM as nametables aren't emulated
+    fn mirroring(&self) -> Mirroring {
+        match (self.control >> 2) & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        (0x400*self.chrbanks[location >> 10] + (location & 0x3FF)) % self.ines.chrrom.len()
+    }
+}
+
+impl Mapper for VRC6 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = VRC6_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbank16: self.prgbank16,
+            prgbank8: self.prgbank8,
+            chrbanks: self.chrbanks,
+            control: self.control,
+            irq: self.irq.clone()
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: VRC6_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbank16 = x.prgbank16;
+        self.prgbank8 = x.prgbank8;
+        self.chrbanks = x.chrbanks;
+        self.control = x.control;
+        self.irq = x.irq;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        match location {
+            0x6000...0x7FFF if self.control & 0x80 != 0 => prgram_read(&self.prgram,location - 0x6000),
+            0x8000...0xBFFF => self.ines.prgrom[(0x4000*self.prgbank16 + location - 0x8000) % len],
+            0xC000...0xDFFF => self.ines.prgrom[(0x2000*self.prgbank8 + location - 0xC000) % len],
+            0xE000...0xFFFF => self.ines.prgrom[(len - 0x2000 + (location & 0x1FFF)) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        let register = if self.swapped_lines {
+            location & 0xF000 | (location & 1) << 1 | (location & 2) >> 1
+        } else {
+            location & 0xF003
+        };
+        match register {
+            0x6000...0x7FFF if self.control & 0x80 != 0 => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
+            0x8000...0x8003 => self.prgbank16 = what as usize & 0xF,
+            0xB003 => self.control = what,
+            0x9000...0xB002 if register & 3 != 3 => self.chip_writes.push(ChipWrite::Vrc6 {
+                osc: (register >> 12) as i32 - 9,
+                reg: (register & 3) as i32,
+                what: what
+            }),
+            0xC000...0xC003 => self.prgbank8 = what as usize & 0x1F,
+            0xD000...0xE003 => {
+                let bank = ((register as usize >> 12) - 0xD) * 4 + (register as usize & 3);
+                self.chrbanks[bank] = what as usize;
+            }
+            0xF000 => self.irq.latch = what,
+            0xF001 => self.irq.write_control(what),
+            0xF002 => self.irq.acknowledge(),
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+    fn interrupt(&mut self) -> bool {
+        self.irq.pending
+    }
+    fn chip_writes(&mut self) -> Vec<ChipWrite> {
+        std::mem::replace(&mut self.chip_writes,vec![])
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//Konami VRC7 (85)
+//https://wiki.nesdev.com/w/index.php/VRC7
+//the second register in each $x000 block is on A4 (VRC7a, submapper 2) or A3 (VRC7b,
+//submapper 1); without a submapper both are watched
+struct VRC7 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    register_line: u16,
+    prgbanks: [usize;3],
+    chrbanks: [usize;8],
+    control: u8, //$E000
+    irq: VrcIrq,
+    audio: Vrc7Audio
+}
+
+#[derive(Serialize,Deserialize)]
+struct VRC7_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbanks: [usize;3],
+    chrbanks: [usize;8],
+    control: u8,
+    irq: VrcIrq,
+    audio: Vrc7Audio
+}
+
+impl VRC7 {
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+    fn chr_index(&self,location:usize) -> usize {
+        (0x400*self.chrbanks[location >> 10] + (location & 0x3FF)) % self.ines.chrrom.len()
+    }
+    //$x000 or $x010
+    fn register(&self,location:u16) -> u16 {
+        location & 0xF000 | if location & self.register_line != 0 { 0x10 } else { 0 }
+    }
+    //bit 6 of $E000 holds the sound chip in reset
+    fn sound_enabled(&self) -> bool {
+        self.control & 0x40 == 0
+    }
+}
+
+impl Mapper for VRC7 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = VRC7_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbanks: self.prgbanks,
+            chrbanks: self.chrbanks,
+            control: self.control,
+            irq: self.irq.clone(),
+            audio: self.audio.clone()
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: VRC7_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbanks = x.prgbanks;
+        self.chrbanks = x.chrbanks;
+        self.control = x.control;
+        self.irq = x.irq;
+        self.audio = x.audio;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        match location {
+            0x6000...0x7FFF if self.control & 0x80 != 0 => prgram_read(&self.prgram,location - 0x6000),
+            0x8000...0xDFFF => self.ines.prgrom[(0x2000*self.prgbanks[(location - 0x8000) >> 13] + (location & 0x1FFF)) % len],
+            0xE000...0xFFFF => self.ines.prgrom[(len - 0x2000 + (location & 0x1FFF)) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x6000...0x7FFF if self.control & 0x80 != 0 => prgram_write(&mut self.prgram,location as usize - 0x6000,what),
+            //the sound ports decode A5 as well
+            0x9010 | 0x9030 if self.sound_enabled() => self.audio.write(location,what),
+            0x8000...0xFFFF => match self.register(location) {
+                0x8000 => self.prgbanks[0] = what as usize & 0x3F,
+                0x8010 => self.prgbanks[1] = what as usize & 0x3F,
+                0x9000 => self.prgbanks[2] = what as usize & 0x3F,
+                register @ 0xA000...0xD010 => {
+                    let bank = ((register as usize >> 12) - 0xA) * 2 + (register as usize >> 4 & 1);
+                    self.chrbanks[bank] = what as usize;
+                }
+                0xE000 => {
+                    self.control = what;
+                    if !self.sound_enabled() {
+                        self.audio = Vrc7Audio::new();
+                    }
+                }
+                0xE010 => self.irq.latch = what,
+                0xF000 => self.irq.write_control(what),
+                0xF010 => self.irq.acknowledge(),
+                _ => ()
+            },
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        if self.sound_enabled() {
+            self.audio.cycle();
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.irq.pending
+    }
+    fn audio_output(&self) -> i32 {
+        if self.sound_enabled() { self.audio.output() } else { 0 }
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//Namco 163 (19)
+//https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+//the 128 bytes of internal RAM are also the sound chip's registers and wavetables; the board
+//keeps them after PRG-RAM in prgram so they're saved with it, and copies writes to the blargg chip
+pub struct N163 {
+    ines: INES,
+    prgram: Vec<u8>,
+    prgram_size: usize,
+    nametables:[[u8;0x400];2],
+    prgbanks: [usize;3],
+    chrbanks: [usize;12], //$0000-$1FFF in 1 KiB, then $2000-$2FFF
+    chrram_disabled: [bool;2], //CHR banks $E0-$FF are CIRAM unless disabled, per pattern table
+    ramprotect: u8,
+    address: u8, //internal RAM address, auto-incremented with bit 7
+    irqcounter: u16,
+    irqenabled: bool,
+    interrupt: bool,
+    chip_writes: Vec<ChipWrite>
+}
+
+#[derive(Serialize,Deserialize)]
+struct N163_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    prgbanks: [usize;3],
+    chrbanks: [usize;12],
+    chrram_disabled: [bool;2],
+    ramprotect: u8,
+    address: u8,
+    irqcounter: u16,
+    irqenabled: bool,
+    interrupt: bool
+}
+
+impl N163 {
+    fn new(ines:INES,mut prgram:Vec<u8>) -> N163 {
+        let prgram_size = ines.prgram_size + ines.prgnvram_size;
+        prgram.resize(prgram_size + ines::board_nvram_size(19),0);
+        let mut n163 = N163 {
+            ines: ines,
+            prgram: prgram,
+            prgram_size: prgram_size,
+            nametables: [[0;0x400];2],
+            prgbanks: [0;3],
+            chrbanks: [0;12],
+            chrram_disabled: [false;2],
+            ramprotect: 0,
+            address: 0,
+            irqcounter: 0,
+            irqenabled: false,
+            interrupt: false,
+            chip_writes: vec![]
+        };
+        //the chip starts out with whatever the battery kept
+        for address in 0..0x80 {
+            let what = n163.prgram[prgram_size + address];
+            n163.chip_writes.push(ChipWrite::Namco {address: address as i32,what: what});
+        }
+        n163
+    }
+    //1 KiB of CHR-ROM, or one of the two CIRAM nametables
+    fn bank(&self,slot:usize) -> (bool,usize) {
+        let bank = self.chrbanks[slot];
+        let ciram = bank >= 0xE0 && (slot >= 8 || !self.chrram_disabled[slot >> 2]);
+        (ciram,if ciram { bank & 1 } else { bank })
+    }
+    fn chr_index(&self,bank:usize,location:usize) -> usize {
+        (0x400*bank + (location & 0x3FF)) % self.ines.chrrom.len()
+    }
+    //$F800 needs $40 in the high nibble, then each bit write-protects 2 KiB
+    fn prgram_writable(&self,location:usize) -> bool {
+        self.ramprotect & 0xF0 == 0x40 && self.ramprotect & (1 << ((location - 0x6000) >> 11)) == 0
+    }
+}
+
+impl Mapper for N163 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = N163_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            prgbanks: self.prgbanks,
+            chrbanks: self.chrbanks,
+            chrram_disabled: self.chrram_disabled,
+            ramprotect: self.ramprotect,
+            address: self.address,
+            irqcounter: self.irqcounter,
+            irqenabled: self.irqenabled,
+            interrupt: self.interrupt
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: N163_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.prgbanks = x.prgbanks;
+        self.chrbanks = x.chrbanks;
+        self.chrram_disabled = x.chrram_disabled;
+        self.ramprotect = x.ramprotect;
+        self.address = x.address;
+        self.irqcounter = x.irqcounter;
+        self.irqenabled = x.irqenabled;
+        self.interrupt = x.interrupt;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        match location {
+            0x4800...0x4FFF => {
+                let result = self.prgram[self.prgram_size + (self.address as usize & 0x7F)];
+                if self.address & 0x80 != 0 {
+                    self.address = (self.address.wrapping_add(1) & 0x7F) | 0x80;
+                }
+                result
+            }
+            0x5000...0x57FF => self.irqcounter as u8,
+            0x5800...0x5FFF => (self.irqcounter >> 8) as u8 | (self.irqenabled as u8) << 7,
+            0x6000...0x7FFF => prgram_read(&self.prgram[..self.prgram_size],location - 0x6000),
+            0x8000...0xDFFF => self.ines.prgrom[(0x2000*self.prgbanks[(location - 0x8000) >> 13] + (location & 0x1FFF)) % len],
+            0xE000...0xFFFF => self.ines.prgrom[(len - 0x2000 + (location & 0x1FFF)) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        let location = location as usize;
+        match location {
+            0x4800...0x4FFF => {
+                let address = self.address as usize & 0x7F;
+                self.prgram[self.prgram_size + address] = what;
+                self.chip_writes.push(ChipWrite::Namco {address: address as i32,what: what});
+                if self.address & 0x80 != 0 {
+                    self.address = (self.address.wrapping_add(1) & 0x7F) | 0x80;
+                }
+            }
+            0x5000...0x57FF => {
+                self.irqcounter = self.irqcounter & 0x7F00 | what as u16;
+                self.interrupt = false;
+            }
+            0x5800...0x5FFF => {
+                self.irqcounter = self.irqcounter & 0xFF | (what as u16 & 0x7F) << 8;
+                self.irqenabled = what & 0x80 != 0;
+                self.interrupt = false;
+            }
+            0x6000...0x7FFF if self.prgram_writable(location) => {
+                let size = self.prgram_size;
+                prgram_write(&mut self.prgram[..size],location - 0x6000,what);
+            }
+            0x8000...0xDFFF => self.chrbanks[(location - 0x8000) >> 11] = what as usize,
+            //bit 6 turns the sound off on some boards, which isn't emulated
+            0xE000...0xE7FF => self.prgbanks[0] = what as usize & 0x3F,
+            0xE800...0xEFFF => {
+                self.prgbanks[1] = what as usize & 0x3F;
+                self.chrram_disabled = [what & 0x40 != 0,what & 0x80 != 0];
+            }
+            0xF000...0xF7FF => self.prgbanks[2] = what as usize & 0x3F,
+            0xF800...0xFFFF => {
+                self.ramprotect = what;
+                self.address = what;
+            }
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        match location {
+            0...0x3EFF => {
+                let (ciram,bank) = self.bank((location & 0x2FFF) >> 10);
+                if ciram {
+                    self.nametables[bank][location & 0x3FF]
+                } else {
+                    self.ines.chrrom[self.chr_index(bank,location)]
+                }
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let location = location as usize;
+        match location {
+            0...0x3EFF => {
+                let (ciram,bank) = self.bank((location & 0x2FFF) >> 10);
+                if ciram {
+                    self.nametables[bank][location & 0x3FF] = what;
+                } else if self.ines.chrrom_size == 0 {
+                    let index = self.chr_index(bank,location);
+                    self.ines.chrrom[index] = what;
+                }
+            }
+            _ => ()
+        }
+    }
+    //counts up to $7FFF and stops there with the IRQ held
+    fn cpu_cycle(&mut self) {
+        if self.irqenabled && self.irqcounter < 0x7FFF {
+            self.irqcounter += 1;
+            if self.irqcounter == 0x7FFF {
+                self.interrupt = true;
+            }
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.interrupt
+    }
+    fn chip_writes(&mut self) -> Vec<ChipWrite> {
+        std::mem::replace(&mut self.chip_writes,vec![])
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//Sunsoft FME-7 and 5A/5B (69)
+//https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+struct FME7 {
+    ines: INES,
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    command: u8,
+    prgbank6000: u8, //bit 7 RAM enable, bit 6 RAM instead of ROM
+    prgbanks: [usize;3],
+    chrbanks: [usize;8],
+    mirroring: Mirroring,
+    irqenabled: bool,
+    counterenabled: bool,
+    irqcounter: u16,
+    interrupt: bool,
+    audio: Sunsoft5bAudio
+}
+
+#[derive(Serialize,Deserialize)]
+struct FME7_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    command: u8,
+    prgbank6000: u8,
+    prgbanks: [usize;3],
+    chrbanks: [usize;8],
+    mirroring: Mirroring,
+    irqenabled: bool,
+    counterenabled: bool,
+    irqcounter: u16,
+    interrupt: bool,
+    audio: Sunsoft5bAudio
+}
+
+impl FME7 {
+    fn chr_index(&self,location:usize) -> usize {
+        (0x400*self.chrbanks[location >> 10] + (location & 0x3FF)) % self.ines.chrrom.len()
+    }
+    //the parameter for the command selected at $8000
+    fn command(&mut self,what:u8) {
+        match self.command {
+            0...7 => self.chrbanks[self.command as usize] = what as usize,
+            8 => self.prgbank6000 = what,
+            9...0xB => self.prgbanks[self.command as usize - 9] = what as usize & 0x3F,
+            0xC => self.mirroring = match what & 3 {
+                0 => Mirroring::Vertical,
+                1 => Mirroring::Horizontal,
+                2 => Mirroring::SingleScreenLower,
+                _ => Mirroring::SingleScreenUpper
+            },
+            0xD => {
+                self.irqenabled = what & 1 != 0;
+                self.counterenabled = what & 0x80 != 0;
+                self.interrupt = false;
+            }
+            0xE => self.irqcounter = self.irqcounter & 0xFF00 | what as u16,
+            _ => self.irqcounter = self.irqcounter & 0xFF | (what as u16) << 8
+        }
+    }
+}
+
+impl Mapper for FME7 {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = FME7_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            command: self.command,
+            prgbank6000: self.prgbank6000,
+            prgbanks: self.prgbanks,
+            chrbanks: self.chrbanks,
+            mirroring: self.mirroring,
+            irqenabled: self.irqenabled,
+            counterenabled: self.counterenabled,
+            irqcounter: self.irqcounter,
+            interrupt: self.interrupt,
+            audio: self.audio.clone()
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: FME7_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.command = x.command;
+        self.prgbank6000 = x.prgbank6000;
+        self.prgbanks = x.prgbanks;
+        self.chrbanks = x.chrbanks;
+        self.mirroring = x.mirroring;
+        self.irqenabled = x.irqenabled;
+        self.counterenabled = x.counterenabled;
+        self.irqcounter = x.irqcounter;
+        self.interrupt = x.interrupt;
+        self.audio = x.audio;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        let len = self.ines.prgrom.len();
+        match location {
+            0x6000...0x7FFF if self.prgbank6000 & 0x40 == 0 => {
+                let bank = self.prgbank6000 as usize & 0x3F;
+                self.ines.prgrom[(0x2000*bank + location - 0x6000) % len]
+            }
+            //RAM that isn't enabled is open bus
+            0x6000...0x7FFF if self.prgbank6000 & 0x80 != 0 => {
+                let bank = self.prgbank6000 as usize & 0x3F;
+                prgram_read(&self.prgram,0x2000*bank + location - 0x6000)
+            }
+            0x8000...0xDFFF => self.ines.prgrom[(0x2000*self.prgbanks[(location - 0x8000) >> 13] + (location & 0x1FFF)) % len],
+            0xE000...0xFFFF => self.ines.prgrom[(len - 0x2000 + (location & 0x1FFF)) % len],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x6000...0x7FFF if self.prgbank6000 & 0xC0 == 0xC0 => {
+                let bank = self.prgbank6000 as usize & 0x3F;
+                prgram_write(&mut self.prgram,0x2000*bank + location as usize - 0x6000,what);
+            }
+            0x8000...0x9FFF => self.command = what & 0xF,
+            0xA000...0xBFFF => self.command(what),
+            0xC000...0xFFFF => self.audio.write(location,what),
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring);
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring);
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    //counts down every CPU cycle; the IRQ fires when it wraps from 0 to $FFFF
+    fn cpu_cycle(&mut self) {
+        self.audio.cycle();
+        if self.counterenabled {
+            self.irqcounter = self.irqcounter.wrapping_sub(1);
+            if self.irqcounter == 0xFFFF && self.irqenabled {
+                self.interrupt = true;
+            }
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.interrupt
+    }
+    fn audio_output(&self) -> i32 {
+        self.audio.output()
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+#[derive(Serialize,Deserialize,Clone,Copy,PartialEq)]
+enum EepromState {
+    Idle,
+    Device, //24C02 device select byte
+    Address,
+    Write,
+    Read,
+    Acknowledge, //pulling SDA low for the ninth clock, then on to next
+    ReadAcknowledge //the master's turn to acknowledge a byte that was read
+}
+
+//I2C serial EEPROM, clocked bit by bit through a board register: a 24C02, or the Xicor 24C01
+//which has no device select byte and sends everything LSB first
+//the contents live in the board's savedata so they go to the savefile; this is the protocol state
+#[derive(Serialize,Deserialize,Clone)]
+struct Eeprom {
+    x24c01: bool,
+    scl: bool,
+    sda: bool,
+    output: bool, //what the chip puts on SDA, wired AND with the master
+    state: EepromState,
+    next: EepromState,
+    bits: u8,
+    shift: u8,
+    address: u8
+}
+
+impl Eeprom {
+    fn new(x24c01:bool) -> Eeprom {
+        Eeprom {
+            x24c01: x24c01,
+            scl: false,
+            sda: false,
+            output: true,
+            state: EepromState::Idle,
+            next: EepromState::Idle,
+            bits: 0,
+            shift: 0,
+            address: 0
+        }
+    }
+    fn size(&self) -> usize {
+        if self.x24c01 { 0x80 } else { 0x100 }
+    }
+    fn acknowledge(&mut self,next:EepromState) {
+        self.state = EepromState::Acknowledge;
+        self.next = next;
+        self.output = false;
+    }
+    fn send_bit(&mut self,memory:&[u8]) {
+        let data = memory[self.address as usize];
+        let bit = if self.x24c01 { self.bits } else { 7 - self.bits };
+        self.output = (data >> bit) & 1 != 0;
+        self.bits += 1;
+    }
+    //the master sets both lines at once; SDA changing while SCL stays high is a start or stop
+    fn write(&mut self,memory:&mut [u8],scl:bool,sda:bool) {
+        if self.scl && scl && sda != self.sda {
+            self.state = if sda {
+                EepromState::Idle
+            } else if self.x24c01 {
+                EepromState::Address
+            } else {
+                EepromState::Device
+            };
+            self.bits = 0;
+            self.shift = 0;
+            self.output = true;
+        } else if !self.scl && scl {
+            self.clock_rise(sda);
+        } else if self.scl && !scl {
+            self.clock_fall(memory);
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+    //the chip samples SDA on the rising edge
+    fn clock_rise(&mut self,sda:bool) {
+        match self.state {
+            EepromState::Device | EepromState::Address | EepromState::Write if self.bits < 8 => {
+                if self.x24c01 {
+                    self.shift |= (sda as u8) << self.bits;
+                } else {
+                    self.shift = self.shift << 1 | sda as u8;
+                }
+                self.bits += 1;
+            }
+            //no acknowledge ends a sequential read
+            EepromState::ReadAcknowledge => self.next = if sda { EepromState::Idle } else { EepromState::Read },
+            _ => ()
+        }
+    }
+    //and changes its own output on the falling edge
+    fn clock_fall(&mut self,memory:&mut [u8]) {
+        let size = self.size();
+        match self.state {
+            EepromState::Device if self.bits == 8 => {
+                if self.shift & 0xF0 == 0xA0 {
+                    let next = if self.shift & 1 != 0 { EepromState::Read } else { EepromState::Address };
+                    self.acknowledge(next);
+                } else {
+                    self.state = EepromState::Idle;
+                }
+            }
+            EepromState::Address if self.bits == 8 => {
+                if self.x24c01 {
+                    self.address = self.shift & 0x7F;
+                    let next = if self.shift & 0x80 != 0 { EepromState::Read } else { EepromState::Write };
+                    self.acknowledge(next);
+                } else {
+                    self.address = self.shift;
+                    self.acknowledge(EepromState::Write);
+                }
+            }
+            //writes wrap within a page, 4 bytes on the 24C01 and 8 on the 24C02
+            EepromState::Write if self.bits == 8 => {
+                memory[self.address as usize % size] = self.shift;
+                let page = if self.x24c01 { 3 } else { 7 };
+                self.address = self.address & !page | self.address.wrapping_add(1) & page;
+                self.acknowledge(EepromState::Write);
+            }
+            EepromState::Acknowledge => {
+                self.state = self.next;
+                self.bits = 0;
+                self.shift = 0;
+                self.output = true;
+                if self.state == EepromState::Read {
+                    self.send_bit(memory);
+                }
+            }
+            EepromState::Read if self.bits == 8 => {
+                self.state = EepromState::ReadAcknowledge;
+                self.address = ((self.address as usize + 1) % size) as u8;
+                self.output = true;
+            }
+            EepromState::Read => self.send_bit(memory),
+            EepromState::ReadAcknowledge => {
+                self.state = self.next;
+                self.bits = 0;
+                if self.state == EepromState::Read {
+                    self.send_bit(memory);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+//Bandai FCG-1/FCG-2 and LZ93D50 (16, 153, 157, 159)
+//https://wiki.nesdev.com/w/index.php/INES_Mapper_016
+//FCG-1/2 (16.4) decode the registers at $6000, the LZ93D50 (16.5 and the rest) at $8000; plain
+//mapper 16 could be either so it answers at both. The LZ93D50 boards save to a 24C02 (16, 157)
+//or 24C01 (159) kept after PRG-RAM in savedata, and Datach (157) adds a 24C01 on the game
+//cartridge; the Datach barcode reader isn't emulated and never has a code to send
+struct BandaiFCG {
+    ines: INES,
+    prgram: Vec<u8>,
+    prgram_size: usize,
+    nametables:[[u8;0x400];4],
+    fcg: bool, //registers at $6000, $B/$C write the counter itself
+    lz93d50: bool, //registers at $8000, $B/$C write a latch that $A copies to the counter
+    chrbanks: [usize;8],
+    prgbank: usize,
+    mirroring: u8,
+    irqenabled: bool,
+    irqcounter: u16,
+    irqlatch: u16,
+    interrupt: bool,
+    eeprom_control: u8, //$D: bit 5 SCL, bit 6 SDA, bit 7 releases SDA to read; bit 3 is the Datach 24C01's SCL
+    eeprom: Option<Eeprom>,
+    external_eeprom: Option<Eeprom>
+}
+
+#[derive(Serialize,Deserialize)]
+struct BandaiFCG_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    chrbanks: [usize;8],
+    prgbank: usize,
+    mirroring: u8,
+    irqenabled: bool,
+    irqcounter: u16,
+    irqlatch: u16,
+    interrupt: bool,
+    eeprom_control: u8,
+    eeprom: Option<Eeprom>,
+    external_eeprom: Option<Eeprom>
+}
+
+impl BandaiFCG {
+    fn new(ines:INES,mut prgram:Vec<u8>) -> BandaiFCG {
+        let prgram_size = ines.prgram_size + ines.prgnvram_size;
+        prgram.resize(prgram_size + ines::board_nvram_size(ines.mapper),0);
+        let (fcg,lz93d50) = match (ines.mapper,ines.submapper) {
+            (16,4) => (true,false),
+            (16,5) | (153,_) | (157,_) | (159,_) => (false,true),
+            (_,_) => (true,true)
+        };
+        let eeprom = match (ines.mapper,ines.submapper) {
+            (16,4) | (153,_) => None,
+            (159,_) => Some(Eeprom::new(true)),
+            (_,_) => Some(Eeprom::new(false))
+        };
+        BandaiFCG {
+            external_eeprom: if ines.mapper == 157 { Some(Eeprom::new(true)) } else { None },
+            ines: ines,
+            prgram: prgram,
+            prgram_size: prgram_size,
+            nametables: [[0;0x400];4],
+            fcg: fcg,
+            lz93d50: lz93d50,
+            chrbanks: [0;8],
+            prgbank: 0,
+            mirroring: 0,
+            irqenabled: false,
+            irqcounter: 0,
+            irqlatch: 0,
+            interrupt: false,
+            eeprom_control: 0,
+            eeprom: eeprom
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        match self.mirroring & 3 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper
+        }
+    }
+    //the boards with CHR-RAM don't bank it
+    fn chr_index(&self,location:usize) -> usize {
+        if self.ines.chrrom_size == 0 {
+            location % self.ines.chrrom.len()
+        } else {
+            (0x400*self.chrbanks[location >> 10] + (location & 0x3FF)) % self.ines.chrrom.len()
+        }
+    }
+    //153 uses bit 0 of the CHR registers to pick the 256 KiB half of its PRG-ROM
+    fn prg_index(&self,bank:usize,location:usize) -> usize {
+        let outer = if self.ines.mapper == 153 {
+            (self.chrbanks[..4].iter().fold(0,|outer,bank| outer | bank) & 1) << 4
+        } else {
+            0
+        };
+        (0x4000*(outer | bank) + (location & 0x3FFF)) % self.ines.prgrom.len()
+    }
+    fn prgram_enabled(&self) -> bool {
+        self.ines.mapper == 153 && self.eeprom_control & 0x20 != 0
+    }
+    //SDA as the CPU sees it in bit 4 of $6000-$7FFF
+    fn sda(&self) -> bool {
+        let master = self.eeprom_control & 0xC0 != 0;
+        let eeprom = self.eeprom.as_ref().map_or(true,|eeprom| eeprom.output);
+        let external = self.external_eeprom.as_ref().map_or(true,|eeprom| eeprom.output);
+        master && eeprom && external
+    }
+    fn write_register(&mut self,register:u16,what:u8) {
+        match register {
+            0x0...0x7 => self.chrbanks[register as usize] = what as usize,
+            0x8 => self.prgbank = what as usize & 0xF,
+            0x9 => self.mirroring = what,
+            0xA => {
+                self.irqenabled = what & 1 != 0;
+                if self.lz93d50 {
+                    self.irqcounter = self.irqlatch;
+                }
+                self.interrupt = false;
+            }
+            0xB => {
+                if self.lz93d50 {
+                    self.irqlatch = self.irqlatch & 0xFF00 | what as u16;
+                }
+                if self.fcg {
+                    self.irqcounter = self.irqcounter & 0xFF00 | what as u16;
+                }
+            }
+            0xC => {
+                if self.lz93d50 {
+                    self.irqlatch = self.irqlatch & 0xFF | (what as u16) << 8;
+                }
+                if self.fcg {
+                    self.irqcounter = self.irqcounter & 0xFF | (what as u16) << 8;
+                }
+            }
+            0xD => {
+                self.eeprom_control = what;
+                let sda = what & 0xC0 != 0;
+                let start = self.prgram_size;
+                if let Some(ref mut eeprom) = self.eeprom {
+                    let size = eeprom.size();
+                    eeprom.write(&mut self.prgram[start..start + size],what & 0x20 != 0,sda);
+                }
+                if let Some(ref mut eeprom) = self.external_eeprom {
+                    let start = start + 0x100;
+                    let size = eeprom.size();
+                    eeprom.write(&mut self.prgram[start..start + size],what & 0x08 != 0,sda);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+impl Mapper for BandaiFCG {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = BandaiFCG_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            chrbanks: self.chrbanks,
+            prgbank: self.prgbank,
+            mirroring: self.mirroring,
+            irqenabled: self.irqenabled,
+            irqcounter: self.irqcounter,
+            irqlatch: self.irqlatch,
+            interrupt: self.interrupt,
+            eeprom_control: self.eeprom_control,
+            eeprom: self.eeprom.clone(),
+            external_eeprom: self.external_eeprom.clone()
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: BandaiFCG_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.chrbanks = x.chrbanks;
+        self.prgbank = x.prgbank;
+        self.mirroring = x.mirroring;
+        self.irqenabled = x.irqenabled;
+        self.irqcounter = x.irqcounter;
+        self.irqlatch = x.irqlatch;
+        self.interrupt = x.interrupt;
+        self.eeprom_control = x.eeprom_control;
+        self.eeprom = x.eeprom;
+        self.external_eeprom = x.external_eeprom;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        match location {
+            0x6000...0x7FFF if self.prgram_enabled() => prgram_read(&self.prgram[..self.prgram_size],location - 0x6000),
+            0x6000...0x7FFF if self.eeprom.is_some() => (self.sda() as u8) << 4,
+            0x8000...0xBFFF => self.ines.prgrom[self.prg_index(self.prgbank,location)],
+            0xC000...0xFFFF => self.ines.prgrom[self.prg_index(0xF,location)],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x6000...0x7FFF if self.prgram_enabled() => {
+                let size = self.prgram_size;
+                prgram_write(&mut self.prgram[..size],location as usize - 0x6000,what);
+            }
+            0x6000...0x7FFF if self.fcg => self.write_register(location & 0xF,what),
+            0x8000...0xFFFF if self.lz93d50 => self.write_register(location & 0xF,what),
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index]
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF if self.ines.chrrom_size == 0 => {
+                let index = self.chr_index(location);
+                self.ines.chrrom[index] = what;
+            }
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    //the IRQ fires as the counter goes from 0 to $FFFF
+    fn cpu_cycle(&mut self) {
+        if self.irqenabled {
+            if self.irqcounter == 0 {
+                self.interrupt = true;
+            }
+            self.irqcounter = self.irqcounter.wrapping_sub(1);
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.interrupt
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.prgram
+    }
+}
+
+//Famicom Disk System RAM adapter
+//https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+struct FDS {
+    ines: INES, //prgrom is the BIOS, savedata the packed disk sides
+    prgram: Vec<u8>,
+    nametables:[[u8;0x400];4],
+    audio: FdsAudio,
+    disks: Vec<Vec<u8>>, //sides with gaps, as the drive sees them
+    side: Option<usize>, //None while ejected
+    next_side: usize,
+    insert_delay: u32,
+    dirty: bool,
+
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_enabled: bool,
+    sound_enabled: bool,
+    write_data: u8,
+    read_data: u8,
+    ext_output: u8,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    horizontalmirroring: bool,
+    crc_control: bool,
+    drive_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+
+    position: usize,
+    delay: u32,
+    scanning: bool,
+    end_of_head: bool,
+    gap_ended: bool,
+    crc: u16,
+    previous_crc_control: bool
+}
+
+#[derive(Serialize,Deserialize)]
+struct FDS_Serial {
+    prgram: Vec<u8>,
+    chrram: Vec<u8>,
+    nametables: Vec<Vec<u8>>,
+    audio: FdsAudio,
+    disks: Vec<Vec<u8>>,
+    side: Option<usize>,
+    next_side: usize,
+    insert_delay: u32,
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_enabled: bool,
+    sound_enabled: bool,
+    write_data: u8,
+    read_data: u8,
+    ext_output: u8,
+    motor_on: bool,
+    reset_transfer: bool,
+    read_mode: bool,
+    horizontalmirroring: bool,
+    crc_control: bool,
+    drive_ready: bool,
+    disk_irq_enabled: bool,
+    disk_irq: bool,
+    transfer_complete: bool,
+    position: usize,
+    delay: u32,
+    scanning: bool,
+    end_of_head: bool,
+    gap_ended: bool,
+    crc: u16,
+    previous_crc_control: bool
+}
+
+//about half a second ejected, so the BIOS notices the disk went away
+const DISK_SWAP_CYCLES: u32 = 900000;
+
+impl FDS {
+    fn new(ines:INES) -> FDS {
+        let disks = ines.savedata.chunks(fds::SIDE_SIZE).map(fds::raw_side).collect();
+        FDS {
+            ines: ines,
+            prgram: vec![0;0x8000],
+            nametables: [[0;0x400];4],
+            audio: FdsAudio::new(),
+            disks: disks,
+            side: Some(0),
+            next_side: 0,
+            insert_delay: 0,
+            dirty: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_enabled: true,
+            sound_enabled: true,
+            write_data: 0,
+            read_data: 0,
+            ext_output: 0,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            horizontalmirroring: false,
+            crc_control: false,
+            drive_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            transfer_complete: false,
+            position: 0,
+            delay: 0,
+            scanning: false,
+            end_of_head: true,
+            gap_ended: false,
+            crc: 0,
+            previous_crc_control: false
+        }
+    }
+    fn mirroring(&self) -> Mirroring {
+        if self.horizontalmirroring {
+            Mirroring::Horizontal
+        } else {
+            Mirroring::Vertical
+        }
+    }
+    //copy written sides back into the savedata
+    fn flush(&mut self) {
+        if self.dirty {
+            for (i,disk) in self.disks.iter().enumerate() {
+                let start = i * fds::SIDE_SIZE;
+                self.ines.savedata[start..start + fds::SIDE_SIZE].copy_from_slice(&fds::pack_side(disk));
+            }
+            self.dirty = false;
+        }
+    }
+    fn update_crc(&mut self,what:u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if what & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+    //one byte passes under the head every 150 cycles or so
+    fn clock_drive(&mut self) {
+        if self.side.is_none() && self.insert_delay > 0 {
+            self.insert_delay -= 1;
+            if self.insert_delay == 0 {
+                self.side = Some(self.next_side);
+            }
+        }
+        let side = match self.side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = 50000;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+        self.scanning = true;
+        let mut irq = self.disk_irq_enabled;
+        if !self.drive_ready {
+            self.crc = 0;
+        }
+        if self.read_mode {
+            let data = self.disks[side][self.position];
+            if !self.drive_ready {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                //the start mark ends the gap
+                self.gap_ended = true;
+                irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = data;
+                self.disk_irq |= irq;
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete = true;
+                data = self.write_data;
+                self.disk_irq |= irq;
+            }
+            if !self.drive_ready {
+                data = 0;
+            }
+            if !self.crc_control {
+                self.update_crc(data);
+            } else {
+                if !self.previous_crc_control {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.disks[side][self.position] = data;
+            self.dirty = true;
+            self.gap_ended = false;
+        }
+        self.previous_crc_control = self.crc_control;
+        self.position += 1;
+        if self.position >= self.disks[side].len() {
+            self.motor_on = false;
+        } else {
+            self.delay = 150;
+        }
+    }
+}
+
+impl Mapper for FDS {
+    fn serialize(&self) -> Vec<u8> {
+        let vnametables = self.nametables.iter().map(|table| table.to_vec()).collect();
+        let serial = FDS_Serial {
+            prgram: self.prgram.clone(),
+            chrram: chrram(&self.ines),
+            nametables: vnametables,
+            audio: self.audio.clone(),
+            disks: self.disks.clone(),
+            side: self.side,
+            next_side: self.next_side,
+            insert_delay: self.insert_delay,
+            irq_reload: self.irq_reload,
+            irq_counter: self.irq_counter,
+            irq_repeat: self.irq_repeat,
+            irq_enabled: self.irq_enabled,
+            timer_irq: self.timer_irq,
+            disk_enabled: self.disk_enabled,
+            sound_enabled: self.sound_enabled,
+            write_data: self.write_data,
+            read_data: self.read_data,
+            ext_output: self.ext_output,
+            motor_on: self.motor_on,
+            reset_transfer: self.reset_transfer,
+            read_mode: self.read_mode,
+            horizontalmirroring: self.horizontalmirroring,
+            crc_control: self.crc_control,
+            drive_ready: self.drive_ready,
+            disk_irq_enabled: self.disk_irq_enabled,
+            disk_irq: self.disk_irq,
+            transfer_complete: self.transfer_complete,
+            position: self.position,
+            delay: self.delay,
+            scanning: self.scanning,
+            end_of_head: self.end_of_head,
+            gap_ended: self.gap_ended,
+            crc: self.crc,
+            previous_crc_control: self.previous_crc_control
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: FDS_Serial = deserialize(data).unwrap();
+        self.prgram.copy_from_slice(&x.prgram);
+        set_chrram(&mut self.ines,&x.chrram);
+        for (table,data) in self.nametables.iter_mut().zip(x.nametables.iter()) {
+            table.copy_from_slice(data);
+        }
+        self.audio = x.audio;
+        self.disks = x.disks;
+        self.side = x.side;
+        self.next_side = x.next_side;
+        self.insert_delay = x.insert_delay;
+        self.irq_reload = x.irq_reload;
+        self.irq_counter = x.irq_counter;
+        self.irq_repeat = x.irq_repeat;
+        self.irq_enabled = x.irq_enabled;
+        self.timer_irq = x.timer_irq;
+        self.disk_enabled = x.disk_enabled;
+        self.sound_enabled = x.sound_enabled;
+        self.write_data = x.write_data;
+        self.read_data = x.read_data;
+        self.ext_output = x.ext_output;
+        self.motor_on = x.motor_on;
+        self.reset_transfer = x.reset_transfer;
+        self.read_mode = x.read_mode;
+        self.horizontalmirroring = x.horizontalmirroring;
+        self.crc_control = x.crc_control;
+        self.drive_ready = x.drive_ready;
+        self.disk_irq_enabled = x.disk_irq_enabled;
+        self.disk_irq = x.disk_irq;
+        self.transfer_complete = x.transfer_complete;
+        self.position = x.position;
+        self.delay = x.delay;
+        self.scanning = x.scanning;
+        self.end_of_head = x.end_of_head;
+        self.gap_ended = x.gap_ended;
+        self.crc = x.crc;
+        self.previous_crc_control = x.previous_crc_control;
+        //the disks may differ from what was last saved
+        self.dirty = true;
+        self.flush();
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        let location = location as usize;
+        match location {
+            0x4030 if self.disk_enabled => {
+                let status = (self.timer_irq as u8) | (self.transfer_complete as u8) << 1;
+                self.timer_irq = false;
+                self.disk_irq = false;
+                self.transfer_complete = false;
+                status
+            }
+            0x4031 if self.disk_enabled => {
+                self.disk_irq = false;
+                self.transfer_complete = false;
+                self.read_data
+            }
+            0x4032 if self.disk_enabled => {
+                let ejected = self.side.is_none() as u8;
+                let not_ready = (self.side.is_none() || !self.scanning) as u8;
+                0x40 | ejected | not_ready << 1 | ejected << 2
+            }
+            0x4033 if self.disk_enabled => self.ext_output & 0x7F | 0x80, //battery is good
+            0x4040...0x4092 if self.sound_enabled => self.audio.read(location as u16),
+            0x6000...0xDFFF => self.prgram[location - 0x6000],
+            0xE000...0xFFFF => self.ines.prgrom[location - 0xE000],
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x4020 => self.irq_reload = self.irq_reload & 0xFF00 | what as u16,
+            0x4021 => self.irq_reload = self.irq_reload & 0xFF | (what as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = what & 1 != 0;
+                self.irq_enabled = what & 2 != 0 && self.disk_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_enabled = what & 1 != 0;
+                self.sound_enabled = what & 2 != 0;
+                if !self.disk_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024 if self.disk_enabled => {
+                self.write_data = what;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 if self.disk_enabled => {
+                self.motor_on = what & 1 != 0;
+                self.reset_transfer = what & 2 != 0;
+                self.read_mode = what & 4 != 0;
+                self.horizontalmirroring = what & 8 != 0;
+                self.crc_control = what & 0x10 != 0;
+                self.drive_ready = what & 0x40 != 0;
+                self.disk_irq_enabled = what & 0x80 != 0;
+                self.disk_irq = false;
+                self.flush();
+            }
+            0x4026 if self.disk_enabled => self.ext_output = what,
+            0x4040...0x408A if self.sound_enabled => self.audio.write(location,what),
+            0x6000...0xDFFF => self.prgram[location as usize - 0x6000] = what,
+            _ => ()
+        }
+    }
+    fn vram_contents(&mut self,location:u16) -> u8 {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => self.ines.chrrom[location],
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location]
+            }
+            _ => 0
+        }
+    }
+    fn set_vram_contents(&mut self,location:u16,what:u8) {
+        let mut location = location as usize;
+        match location {
+            0...0x1FFF => self.ines.chrrom[location] = what,
+            0x2000...0x3EFF => {
+                let nametable = nametable(location,self.mirroring());
+                location &= 0x3FF;
+                self.nametables[nametable][location] = what;
+            }
+            _ => ()
+        }
+    }
+    fn cpu_cycle(&mut self) {
+        self.audio.cycle();
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.timer_irq = true;
+                self.irq_counter = self.irq_reload;
+                self.irq_enabled = self.irq_repeat;
+            } else {
+                self.irq_counter -= 1;
+            }
+        }
+        self.clock_drive();
+    }
+    //the IRQ line stays low until $4030/$4031 acknowledge it
+    fn interrupt(&mut self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+    fn audio_output(&self) -> i32 {
+        self.audio.output()
+    }
+    //eject, then insert the next side
+    fn switch_disk_side(&mut self) {
+        self.flush();
+        self.next_side = match self.side {
+            Some(side) => side + 1,
+            None => self.next_side + 1
+        } % self.disks.len();
+        self.side = None;
+        self.insert_delay = DISK_SWAP_CYCLES;
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &self.ines.savedata
+    }
+}
+
+/*
+NSF player. A small driver lives at $4100: the mapper answers the song number at $4100,
+the region at $4101, starts the PLAY timer on a write to $4102 and acknowledges its IRQ on
+a read of $4103. The driver calls INIT and then PLAY from the timer's IRQ.
+*/
+const NSF_DRIVER: u16 = 0x4110;
+
+//the driver and its NMI, RESET and IRQ vectors
+fn nsf_driver(init:u16,play:u16) -> (Vec<u8>,[u16;3]) {
+    let reset = vec![
+        0x78, //SEI
+        0xD8, //CLD
+        0xA2,0xFF, //LDX #$FF
+        0x9A, //TXS
+        0xA9,0x00, //LDA #0
+        0xAA, //TAX
+        0x95,0x00, //clear: STA $00,X
+        0x9D,0x00,0x01, //STA $0100,X
+        0x9D,0x00,0x02, //STA $0200,X
+        0x9D,0x00,0x03, //STA $0300,X
+        0x9D,0x00,0x04, //STA $0400,X
+        0x9D,0x00,0x05, //STA $0500,X
+        0x9D,0x00,0x06, //STA $0600,X
+        0x9D,0x00,0x07, //STA $0700,X
+        0xE8, //INX
+        0xD0,0xE6, //BNE clear
+        0x9D,0x00,0x40, //silence: STA $4000,X
+        0xE8, //INX
+        0xE0,0x14, //CPX #$14
+        0xD0,0xF8, //BNE silence
+        0xA9,0x0F, //LDA #$0F
+        0x8D,0x15,0x40, //STA $4015
+        0xA9,0x40, //LDA #$40
+        0x8D,0x17,0x40, //STA $4017
+        0xAD,0x00,0x41, //LDA $4100
+        0xAE,0x01,0x41, //LDX $4101
+        0x20,init as u8,(init >> 8) as u8, //JSR init
+        0x8D,0x02,0x41, //STA $4102
+        0x58 //CLI
+    ];
+    let idle = NSF_DRIVER + reset.len() as u16;
+    let irq = idle + 3;
+    let mut driver = reset;
+    driver.extend_from_slice(&[
+        0x4C,idle as u8,(idle >> 8) as u8, //idle: JMP idle
+        0x48, //irq: PHA
+        0x8A, //TXA
+        0x48, //PHA
+        0x98, //TYA
+        0x48, //PHA
+        0xAD,0x03,0x41, //LDA $4103
+        0x20,play as u8,(play >> 8) as u8, //JSR play
+        0x68, //PLA
+        0xA8, //TAY
+        0x68, //PLA
+        0xAA, //TAX
+        0x68, //PLA
+        0x40, //RTI
+        0x40 //nmi: RTI
+    ]);
+    let nmi = NSF_DRIVER + driver.len() as u16 - 1;
+    (driver,[nmi,NSF_DRIVER,irq])
+}
+
+struct NSF {
+    ines: INES,
+    header: NsfHeader,
+    driver: Vec<u8>,
+    vectors: [u16;3],
+    fds: bool, //FDS tunes run from 40 KiB of RAM at $6000-$FFFF
+    mmc5: bool,
+    vrc6: bool,
+    n163: bool,
+    sunsoft5b: bool,
+    vrc7: bool,
+    song: usize,
+    banks: [usize;10], //4 KiB pages at $6000-$FFFF
+    ram: Vec<u8>,
+    audio: FdsAudio,
+    mmc5_audio: Mmc5Audio,
+    sunsoft5b_audio: Sunsoft5bAudio,
+    vrc7_audio: Vrc7Audio,
+    exram: Vec<u8>,
+    multiplicand: u8,
+    multiplier: u8,
+    chip_writes: Vec<ChipWrite>,
+    n163_ram: Vec<u8>,
+    n163_address: u8,
+    period: u32,
+    counter: u32,
+    playing: bool,
+    irq: bool
+}
+
+#[derive(Serialize,Deserialize)]
+struct NSF_Serial {
+    song: usize,
+    banks: Vec<usize>,
+    ram: Vec<u8>,
+    audio: FdsAudio,
+    mmc5_audio: Mmc5Audio,
+    sunsoft5b_audio: Sunsoft5bAudio,
+    vrc7_audio: Vrc7Audio,
+    exram: Vec<u8>,
+    multiplicand: u8,
+    multiplier: u8,
+    n163_ram: Vec<u8>,
+    n163_address: u8,
+    counter: u32,
+    playing: bool,
+    irq: bool
+}
+
+impl NSF {
+    fn new(mut ines:INES) -> NSF {
+        let header = ines.nsf.take().unwrap();
+        let speed = if header.speed == 0 { 16639 } else { header.speed as u64 };
+        let fds = header.expansion & nsf::FDS != 0;
+        let (driver,vectors) = nsf_driver(header.init,header.play);
+        let mut nsf = NSF {
+            driver: driver,
+            vectors: vectors,
+            fds: fds,
+            mmc5: header.expansion & nsf::MMC5 != 0,
+            vrc6: header.expansion & nsf::VRC6 != 0,
+            n163: header.expansion & nsf::N163 != 0,
+            sunsoft5b: header.expansion & nsf::SUNSOFT5B != 0,
+            vrc7: header.expansion & nsf::VRC7 != 0,
+            song: header.start_song.min(header.songs) as usize - 1,
+            banks: [0;10],
+            ram: vec![0;if fds { 0xA000 } else { 0x2000 }],
+            audio: FdsAudio::new(),
+            mmc5_audio: Mmc5Audio::new(),
+            sunsoft5b_audio: Sunsoft5bAudio::new(),
+            vrc7_audio: Vrc7Audio::new(),
+            exram: vec![0;0x400],
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            chip_writes: vec![],
+            n163_ram: vec![0;0x80],
+            n163_address: 0,
+            period: (speed * 1789773 / 1000000) as u32,
+            counter: 0,
+            playing: false,
+            irq: false,
+            ines: ines,
+            header: header
+        };
+        nsf.start_track();
+        nsf
+    }
+    fn start_track(&mut self) {
+        for x in self.ram.iter_mut() {
+            *x = 0;
+        }
+        for page in 0..10 {
+            let bank = if self.header.bankswitched {
+                match page {
+                    0...1 => self.header.banks[page + 6] as usize,
+                    _ => self.header.banks[page - 2] as usize
+                }
+            } else if self.fds {
+                page
+            } else {
+                page.wrapping_sub(2)
+            };
+            if page >= 2 || self.fds {
+                self.set_bank(page,bank);
+            }
+        }
+        self.audio = FdsAudio::new();
+        self.mmc5_audio = Mmc5Audio::new();
+        self.sunsoft5b_audio = Sunsoft5bAudio::new();
+        self.vrc7_audio = Vrc7Audio::new();
+        for x in self.exram.iter_mut() {
+            *x = 0;
+        }
+        if self.n163 {
+            //the reset that follows doesn't clear the chip
+            for address in 0..0x80 {
+                self.n163_ram[address] = 0;
+                self.chip_writes.push(ChipWrite::Namco {address: address as i32,what: 0});
+            }
+        }
+        self.n163_address = 0;
+        self.counter = 0;
+        self.playing = false;
+        self.irq = false;
+    }
+    //FDS tunes get the bank copied into RAM
+    fn set_bank(&mut self,page:usize,bank:usize) {
+        let pages = self.ines.prgrom.len() / 0x1000;
+        let bank = bank % pages;
+        self.banks[page] = bank;
+        if self.fds {
+            self.ram[page*0x1000..(page + 1)*0x1000].copy_from_slice(&self.ines.prgrom[bank*0x1000..(bank + 1)*0x1000]);
+        }
+    }
+}
+
+impl Mapper for NSF {
+    fn serialize(&self) -> Vec<u8> {
+        let serial = NSF_Serial {
+            song: self.song,
+            banks: self.banks.to_vec(),
+            ram: self.ram.clone(),
+            audio: self.audio.clone(),
+            mmc5_audio: self.mmc5_audio.clone(),
+            sunsoft5b_audio: self.sunsoft5b_audio.clone(),
+            vrc7_audio: self.vrc7_audio.clone(),
+            exram: self.exram.clone(),
+            multiplicand: self.multiplicand,
+            multiplier: self.multiplier,
+            n163_ram: self.n163_ram.clone(),
+            n163_address: self.n163_address,
+            counter: self.counter,
+            playing: self.playing,
+            irq: self.irq
+        };
+        serialize(&serial).unwrap()
+    }
+    fn deserialize(&mut self,data:&[u8]) {
+        let x: NSF_Serial = deserialize(data).unwrap();
+        self.song = x.song;
+        self.banks.copy_from_slice(&x.banks);
+        self.ram.copy_from_slice(&x.ram);
+        self.audio = x.audio;
+        self.mmc5_audio = x.mmc5_audio;
+        self.sunsoft5b_audio = x.sunsoft5b_audio;
+        self.vrc7_audio = x.vrc7_audio;
+        self.exram.copy_from_slice(&x.exram);
+        self.multiplicand = x.multiplicand;
+        self.multiplier = x.multiplier;
+        self.n163_ram.copy_from_slice(&x.n163_ram);
+        self.n163_address = x.n163_address;
+        self.counter = x.counter;
+        self.playing = x.playing;
+        self.irq = x.irq;
+    }
+    fn contents(&mut self,location:u16) -> u8 {
+        match location {
+            0x4040...0x4092 if self.fds => self.audio.read(location),
+            0x4800 if self.n163 => {
+                let result = self.n163_ram[self.n163_address as usize & 0x7F];
+                if self.n163_address & 0x80 != 0 {
+                    self.n163_address = (self.n163_address.wrapping_add(1) & 0x7F) | 0x80;
+                }
+                result
+            }
+            0x5010 | 0x5015 if self.mmc5 => self.mmc5_audio.read(location),
+            0x5205 if self.mmc5 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 if self.mmc5 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00...0x5FF5 if self.mmc5 => self.exram[location as usize - 0x5C00],
+            0x4100 => self.song as u8,
+            0x4101 => 0, //NTSC
+            0x4103 => {
+                self.irq = false;
+                0
+            }
+            0x4110...0x41FF => *self.driver.get((location - NSF_DRIVER) as usize).unwrap_or(&0),
+            0xFFFA...0xFFFF => {
+                let vector = self.vectors[(location as usize - 0xFFFA) / 2];
+                if location & 1 == 0 { vector as u8 } else { (vector >> 8) as u8 }
+            }
+            0x6000...0x7FFF => self.ram[location as usize - 0x6000],
+            0x8000...0xFFFF if self.fds => self.ram[location as usize - 0x6000],
+            0x8000...0xFFFF => {
+                let location = location as usize - 0x8000;
+                self.ines.prgrom[self.banks[2 + (location >> 12)]*0x1000 + (location & 0xFFF)]
+            }
+            _ => 0
+        }
+    }
+    fn set_contents(&mut self,location:u16,what:u8) {
+        match location {
+            0x4040...0x408A if self.fds => self.audio.write(location,what),
+            0x4102 => self.playing = true,
+            0x4800 if self.n163 => {
+                let address = self.n163_address as usize & 0x7F;
+                self.n163_ram[address] = what;
+                self.chip_writes.push(ChipWrite::Namco {address: address as i32,what: what});
+                if self.n163_address & 0x80 != 0 {
+                    self.n163_address = (self.n163_address.wrapping_add(1) & 0x7F) | 0x80;
+                }
+            }
+            0xF800 if self.n163 => self.n163_address = what,
+            0x5000...0x5015 if self.mmc5 => self.mmc5_audio.write(location,what),
+            0x5205 if self.mmc5 => self.multiplicand = what,
+            0x5206 if self.mmc5 => self.multiplier = what,
+            0x5C00...0x5FF5 if self.mmc5 => self.exram[location as usize - 0x5C00] = what,
+            0x9000...0x9002 | 0xA000...0xA002 | 0xB000...0xB002 if self.vrc6 => self.chip_writes.push(ChipWrite::Vrc6 {
+                osc: (location >> 12) as i32 - 9,
+                reg: (location & 3) as i32,
+                what: what
+            }),
+            0x9010 | 0x9030 if self.vrc7 => self.vrc7_audio.write(location,what),
+            0xC000 | 0xE000 if self.sunsoft5b => self.sunsoft5b_audio.write(location,what),
+            0x5FF6...0x5FF7 if self.fds => self.set_bank(location as usize - 0x5FF6,what as usize),
+            0x5FF8...0x5FFF => self.set_bank(location as usize - 0x5FF6,what as usize),
+            0x6000...0x7FFF => self.ram[location as usize - 0x6000] = what,
+            0x8000...0xFFFF if self.fds => self.ram[location as usize - 0x6000] = what,
+            _ => ()
+        }
+    }
+    //nothing is drawn
+    fn vram_contents(&mut self,_location:u16) -> u8 {
+        0
+    }
+    fn set_vram_contents(&mut self,_location:u16,_what:u8) {
+
+    }
+    fn cpu_cycle(&mut self) {
+        if self.fds {
+            self.audio.cycle();
+        }
+        if self.mmc5 {
+            self.mmc5_audio.cycle();
+        }
+        if self.sunsoft5b {
+            self.sunsoft5b_audio.cycle();
+        }
+        if self.vrc7 {
+            self.vrc7_audio.cycle();
+        }
+        if self.playing {
+            self.counter += 1;
+            if self.counter >= self.period {
+                self.counter = 0;
+                self.irq = true;
+            }
+        }
+    }
+    fn interrupt(&mut self) -> bool {
+        self.irq
+    }
+    fn audio_output(&self) -> i32 {
+        self.audio.output() + self.mmc5_audio.output() + self.sunsoft5b_audio.output() + self.vrc7_audio.output()
+    }
+    fn chip_writes(&mut self) -> Vec<ChipWrite> {
+        std::mem::replace(&mut self.chip_writes,vec![])
+    }
+    fn change_track(&mut self,delta:i32) -> Option<(usize,usize)> {
+        let songs = self.header.songs as i32;
+        if delta != 0 {
+            self.song = ((self.song as i32 + delta) % songs + songs) as usize % songs as usize;
+            self.start_track();
+        }
+        Some((self.song + 1,songs as usize))
+    }
+    fn get_savedata(&self) -> &[u8] {
+        &[]
+    }
+}
+
+pub fn get_mapper(ines:INES) -> Result<Rc<RefCell<Mapper>>,RomError> {
+    if ines.nsf.is_some() {
+        return Ok(Rc::new(RefCell::new(NSF::new(ines))));
+    }
+    let mut prgram = ines.savedata.clone();
+    if ines.trainer.len() != 0 {
+        if prgram.len() < 0x2000 {
+            prgram.resize(0x2000,0);
+        }
+        prgram[0x1000..0x1200].copy_from_slice(&ines.trainer);
+    }
+    match ines.mapper {
+        0 => Ok(Rc::new(RefCell::new(NROM {ines:ines,prgram:prgram,nametables:[[0;0x400];4]}))),
+        1 => {
+            Ok(Rc::new(RefCell::new(
+                MMC1 {
+                    ines:ines,
+                    prgram:prgram,
+                    nametables:[[0;0x400];4],
+                    mirroringtype:0,
+                    prgrommode:3,
+                    chrrommode:0,
+                    chr0bank: 0,
+                    chr1bank: 0,
+                    enableprgram: true,
+                    prgbank: 0,
+                    input: 1 << 4,
+                })))
+        }
+        4 | 88 | 95 | 118 | 119 | 154 | 206 => Ok(Rc::new(RefCell::new(MMC3::new(ines,prgram)))),
+        2 | 94 => {
+            Ok(Rc::new(RefCell::new(
+                UxROM {
+                    un1rom: ines.mapper == 94,
+                    bus_conflicts: ines.mapper == 2 && ines.submapper == 2,
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    prgbank: 0
+                })))
+        }
+        3 | 7 | 11 | 34 | 66 => {
+            //NES 2.0 submappers say which boards have bus conflicts; otherwise go by what most carts did
+            let nina = ines.mapper == 34 && (ines.submapper == 1 || ines.submapper == 0 && ines.chrrom_size != 0);
+            let bus_conflicts = match ines.mapper {
+                3 => ines.submapper != 1,
+                7 => ines.submapper == 2,
+                34 => !nina,
+                _ => true
+            };
+            let mirroring = if ines.mapper == 7 { Mirroring::SingleScreenLower } else { header_mirroring(&ines) };
+            Ok(Rc::new(RefCell::new(
+                Discrete {
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    prgbank: 0,
+                    chrbank0: 0,
+                    chrbank1: 1,
+                    mirroring: mirroring,
+                    nina: nina,
+                    bus_conflicts: bus_conflicts
+                })))
+        }
+        9 | 10 => {
+            Ok(Rc::new(RefCell::new(
+                MMC2 {
+                    mmc4: ines.mapper == 10,
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    prgbank: 0,
+                    chrbanks: [0;4],
+                    latch0: true,
+                    latch1: true,
+                    horizontalmirroring: false
+                })))
+        }
+        5 => Ok(Rc::new(RefCell::new(MMC5::new(ines,prgram)))),
+        21 | 22 | 23 | 25 => Ok(Rc::new(RefCell::new(VRC24::new(ines,prgram)))),
+        24 | 26 => {
+            Ok(Rc::new(RefCell::new(
+                VRC6 {
+                    swapped_lines: ines.mapper == 26,
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    prgbank16: 0,
+                    prgbank8: 0,
+                    chrbanks: [0;8],
+                    control: 0,
+                    irq: VrcIrq::new(),
+                    chip_writes: vec![]
+                })))
+        }
+        85 => {
+            let register_line = match ines.submapper {
+                1 => 0x08,
+                2 => 0x10,
+                _ => 0x18
+            };
+            Ok(Rc::new(RefCell::new(
+                VRC7 {
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    register_line: register_line,
+                    prgbanks: [0;3],
+                    chrbanks: [0;8],
+                    control: 0,
+                    irq: VrcIrq::new(),
+                    audio: Vrc7Audio::new()
+                })))
+        }
+        19 => Ok(Rc::new(RefCell::new(N163::new(ines,prgram)))),
+        69 => {
+            Ok(Rc::new(RefCell::new(
+                FME7 {
+                    ines: ines,
+                    prgram: prgram,
+                    nametables: [[0;0x400];4],
+                    command: 0,
+                    prgbank6000: 0,
+                    prgbanks: [0;3],
+                    chrbanks: [0;8],
+                    mirroring: Mirroring::Vertical,
+                    irqenabled: false,
+                    counterenabled: false,
+                    irqcounter: 0,
+                    interrupt: false,
+                    audio: Sunsoft5bAudio::new()
+                })))
         }
-        _ => panic!("Bad id: {}",ines.mapper)
+        16 | 153 | 157 | 159 => Ok(Rc::new(RefCell::new(BandaiFCG::new(ines,prgram)))),
+        20 => Ok(Rc::new(RefCell::new(FDS::new(ines)))),
+        _ => Err(RomError::UnsupportedMapper(ines.mapper))
     }
 }
@@ -0,0 +1,92 @@
+use ines::INES;
+use ines::RomError;
+
+/*
+NSF music rips: a 128 byte header followed by the tune's PRG data.
+https://wiki.nesdev.com/w/index.php/NSF
+*/
+
+//expansion chip bits
+pub const VRC6: u8 = 0x01;
+pub const VRC7: u8 = 0x02;
+pub const FDS: u8 = 0x04;
+pub const MMC5: u8 = 0x08;
+pub const N163: u8 = 0x10;
+pub const SUNSOFT5B: u8 = 0x20;
+
+#[derive(Clone)]
+pub struct NsfHeader {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub songs: u8,
+    pub start_song: u8, //1 based
+    pub load: u16,
+    pub init: u16,
+    pub play: u16,
+    pub speed: u16, //NTSC PLAY period in microseconds
+    pub banks: [u8;8], //initial $5FF8-$5FFF values
+    pub bankswitched: bool,
+    pub expansion: u8
+}
+
+pub fn is_nsf(data: &[u8]) -> bool {
+    data.starts_with(b"NESM\x1A")
+}
+
+fn string(field: &[u8]) -> String {
+    let end = field.iter().position(|&x| x == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn word(data: &[u8],at: usize) -> u16 {
+    data[at] as u16 | (data[at + 1] as u16) << 8
+}
+
+//prgrom starts at the first 4 KiB page the tune touches: $x000 of the load address when
+//bankswitched, $8000 otherwise ($6000 for FDS tunes, which run out of RAM)
+pub fn parse(data: &[u8]) -> Result<INES,RomError> {
+    if !is_nsf(data) || data.len() < 128 {
+        return Err(RomError::BadMagic);
+    }
+    let mut banks = [0;8];
+    banks.copy_from_slice(&data[0x70..0x78]);
+    let header = NsfHeader {
+        title: string(&data[0x0E..0x2E]),
+        artist: string(&data[0x2E..0x4E]),
+        copyright: string(&data[0x4E..0x6E]),
+        songs: data[0x06].max(1),
+        start_song: data[0x07].max(1),
+        load: word(data,0x08),
+        init: word(data,0x0A),
+        play: word(data,0x0C),
+        speed: word(data,0x6E),
+        banks: banks,
+        bankswitched: banks.iter().any(|&x| x != 0),
+        expansion: data[0x7B]
+    };
+    let base = if header.bankswitched {
+        header.load & 0xF000
+    } else if header.expansion & FDS != 0 {
+        0x6000
+    } else {
+        0x8000
+    };
+    if header.load < base || data.len() == 128 {
+        return Err(RomError::TruncatedPrg);
+    }
+    let mut prgrom = vec![0;(header.load - base) as usize];
+    prgrom.extend_from_slice(&data[128..]);
+    let pages = (prgrom.len() + 0xFFF) / 0x1000;
+    prgrom.resize(pages * 0x1000,0);
+
+    let mut result = INES::empty();
+    result.prgrom_size = ((prgrom.len() + 0x3FFF) / 0x4000) as u16;
+    result.prgrom = prgrom;
+    result.prgram_size = 0x2000;
+    if header.title.len() != 0 {
+        result.title = Some(header.title.clone());
+    }
+    result.nsf = Some(header);
+    Ok(result)
+}
@@ -0,0 +1,215 @@
+use ines::RomError;
+use crc32fast;
+
+/*
+Soft patches, applied to the whole file before it is parsed.
+IPS: https://zerosoft.zophar.net/ips.php
+UPS: http://www.romhacking.net/documents/392/
+BPS: https://www.romhacking.net/documents/746/
+*/
+
+pub fn apply(patch: &[u8],data: &[u8]) -> Result<Vec<u8>,RomError> {
+    if patch.starts_with(b"PATCH") {
+        ips(patch,data)
+    } else if patch.starts_with(b"UPS1") {
+        ups(patch,data)
+    } else if patch.starts_with(b"BPS1") {
+        bps(patch,data)
+    } else {
+        Err(RomError::BadPatch("not an IPS, UPS or BPS patch"))
+    }
+}
+
+//bounds checked reads, since a bad patch shouldn't take the emulator down
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8,RomError> {
+        let result = *self.data.get(self.position).ok_or(RomError::BadPatch("patch is truncated"))?;
+        self.position += 1;
+        Ok(result)
+    }
+    fn bytes(&mut self,count: usize) -> Result<&'a [u8],RomError> {
+        if self.data.len() - self.position < count {
+            return Err(RomError::BadPatch("patch is truncated"));
+        }
+        self.position += count;
+        Ok(&self.data[self.position - count..self.position])
+    }
+    fn big_endian(&mut self,count: usize) -> Result<usize,RomError> {
+        let mut result = 0;
+        for _ in 0..count {
+            result = result << 8 | self.byte()? as usize;
+        }
+        Ok(result)
+    }
+    //UPS and BPS numbers: 7 bits a byte, high bit ends it, each continuation adds one
+    fn number(&mut self) -> Result<usize,RomError> {
+        let mut result = 0usize;
+        let mut shift = 1usize;
+        loop {
+            let x = self.byte()? as usize;
+            result = result.wrapping_add((x & 0x7F).wrapping_mul(shift));
+            if x & 0x80 != 0 {
+                return Ok(result);
+            }
+            shift = shift.wrapping_shl(7);
+            result = result.wrapping_add(shift);
+        }
+    }
+}
+
+fn ips(patch: &[u8],data: &[u8]) -> Result<Vec<u8>,RomError> {
+    let mut result = data.to_vec();
+    let mut reader = Reader {data: patch,position: 5};
+    loop {
+        let offset = reader.big_endian(3)?;
+        if offset == 0x454F46 { //EOF
+            break;
+        }
+        let size = reader.big_endian(2)?;
+        let (size,run) = if size == 0 {
+            let size = reader.big_endian(2)?;
+            (size,Some(reader.byte()?))
+        } else {
+            (size,None)
+        };
+        if result.len() < offset + size {
+            result.resize(offset + size,0);
+        }
+        match run {
+            Some(value) => for x in result[offset..offset + size].iter_mut() {
+                *x = value;
+            },
+            None => result[offset..offset + size].copy_from_slice(reader.bytes(size)?)
+        }
+    }
+    //an optional truncated length follows EOF
+    if let Ok(length) = reader.big_endian(3) {
+        result.truncate(length);
+    }
+    Ok(result)
+}
+
+fn little_endian(data: &[u8]) -> u32 {
+    data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24
+}
+
+//UPS and BPS end with the source, target and patch CRC32s
+fn check_footer(patch: &[u8],data: &[u8]) -> Result<(u32,usize),RomError> {
+    if patch.len() < 16 {
+        return Err(RomError::BadPatch("patch is truncated"));
+    }
+    let footer = &patch[patch.len() - 12..];
+    if crc32fast::hash(&patch[..patch.len() - 4]) != little_endian(&footer[8..]) {
+        return Err(RomError::BadPatch("patch checksum doesn't match; the patch is corrupt"));
+    }
+    if crc32fast::hash(data) != little_endian(&footer[0..]) {
+        return Err(RomError::BadPatch("ROM checksum doesn't match the one the patch was made for"));
+    }
+    Ok((little_endian(&footer[4..]),patch.len() - 12))
+}
+
+fn check_target(result: Vec<u8>,crc: u32) -> Result<Vec<u8>,RomError> {
+    if crc32fast::hash(&result) != crc {
+        return Err(RomError::BadPatch("patched ROM checksum doesn't match the patch's"));
+    }
+    Ok(result)
+}
+
+//nothing for the NES comes close; this just stops a corrupt size from exhausting memory
+const MAX_TARGET_SIZE: usize = 0x4000000;
+
+fn ups(patch: &[u8],data: &[u8]) -> Result<Vec<u8>,RomError> {
+    let (target_crc,end) = check_footer(patch,data)?;
+    let mut reader = Reader {data: &patch[..end],position: 4};
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(RomError::BadPatch("patched ROM is too large"));
+    }
+    let mut result = data.to_vec();
+    result.resize(target_size,0);
+    let mut position = 0usize;
+    while reader.position < end {
+        position = position.saturating_add(reader.number()?);
+        //xor until (and including) a zero byte
+        loop {
+            let x = reader.byte()?;
+            if position < result.len() {
+                result[position] ^= x;
+            }
+            position += 1;
+            if x == 0 {
+                break;
+            }
+        }
+    }
+    check_target(result,target_crc)
+}
+
+fn bps(patch: &[u8],data: &[u8]) -> Result<Vec<u8>,RomError> {
+    let (target_crc,end) = check_footer(patch,data)?;
+    let mut reader = Reader {data: &patch[..end],position: 4};
+    let _source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    if target_size > MAX_TARGET_SIZE {
+        return Err(RomError::BadPatch("patched ROM is too large"));
+    }
+    let mut result = Vec::with_capacity(target_size);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+    while reader.position < end {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+        match action & 3 {
+            0 => { //source read
+                let start = result.len();
+                result.extend_from_slice(slice(data,start,length)?);
+            }
+            1 => { //target read
+                result.extend_from_slice(reader.bytes(length)?);
+            }
+            2 => { //source copy
+                let offset = reader.number()?;
+                source_offset = relative(source_offset,offset);
+                result.extend_from_slice(slice(data,source_offset,length)?);
+                source_offset += length;
+            }
+            _ => { //target copy, which may overlap what it writes
+                let offset = reader.number()?;
+                target_offset = relative(target_offset,offset);
+                for _ in 0..length {
+                    let x = *slice(&result,target_offset,1)?.first().unwrap();
+                    result.push(x);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if result.len() != target_size {
+        return Err(RomError::BadPatch("patched ROM is the wrong size"));
+    }
+    check_target(result,target_crc)
+}
+
+fn slice(data: &[u8],start: usize,length: usize) -> Result<&[u8],RomError> {
+    match start.checked_add(length) {
+        Some(end) if end <= data.len() => Ok(&data[start..end]),
+        _ => Err(RomError::BadPatch("patch reads outside of the ROM"))
+    }
+}
+
+//copy offsets are stored as a sign bit and a magnitude
+fn relative(base: usize,offset: usize) -> usize {
+    if offset & 1 != 0 {
+        base.wrapping_sub(offset >> 1)
+    } else {
+        base.wrapping_add(offset >> 1)
+    }
+}
@@ -18,8 +18,7 @@ fn get_bit(what:u16,whr:u8) -> u8 {
 #[derive(PartialEq)]
 pub enum PPUStatus {
     Nothing,
-    VBlank,
-    HBlank
+    VBlank
 }
 
 pub struct PPU {
@@ -72,6 +71,7 @@ pub struct PPU {
     sprite_x_counters:[u8;8],
     sprite_attributes:[u8;8],
     sprite_indices:[usize;8],
+    sprite_addresses:[u16;8],
     found: usize,
 
     //screen buffer
@@ -131,6 +131,7 @@ pub struct PPU_Serial { //PPU w/o one reference.... kms
     sprite_x_counters:[u8;8],
     sprite_attributes:[u8;8],
     sprite_indices:[usize;8],
+    sprite_addresses:[u16;8],
     found: usize,
     screen : Vec<u8>,
     read_buffer: u8
@@ -186,6 +187,7 @@ impl PPU {
             sprite_x_counters: self.sprite_x_counters,
             sprite_attributes: self.sprite_attributes,
             sprite_indices: self.sprite_indices,
+            sprite_addresses: self.sprite_addresses,
             found: self.found,
             screen: vscreen,
             read_buffer: self.read_buffer
@@ -235,6 +237,7 @@ impl PPU {
         self.sprite_x_counters = serial.sprite_x_counters;
         self.sprite_attributes = serial.sprite_attributes;
         self.sprite_indices = serial.sprite_indices;
+        self.sprite_addresses = serial.sprite_addresses;
         self.found = serial.found;
         self.screen.copy_from_slice(&serial.screen);
         self.read_buffer = serial.read_buffer;
@@ -283,6 +286,7 @@ impl PPU {
             sprite_x_counters:   [0xFF;8],
             sprite_attributes:   [0xFF;8],
             sprite_indices: [0;8],
+            sprite_addresses: [0;8],
             found: 0,
             screen:[0;256*240*3],
             read_buffer: 0
@@ -348,6 +352,8 @@ impl PPU {
             self.t &= 0xFF00;
             self.t |= what;
             self.v = self.t;
+            //v goes straight out on the address bus, where the mapper can see it
+            self.mapper.borrow_mut().ppu_address(self.v & 0x3FFF);
         }
         self.w = !self.w;
     }
@@ -369,6 +375,7 @@ impl PPU {
     }
     pub fn contents(&mut self,index_: u16) -> u8 {
         let mut index = index_ & 0x3FFF;
+        self.mapper.borrow_mut().ppu_address(index);
         match index {
             0x3F00 ... 0x3FFF => {
                 index &= 0x1f;
@@ -388,6 +395,7 @@ impl PPU {
     }
     pub fn set_contents(&mut self,index_: u16,what:u8) {
         let mut index = index_ & 0x3FFF;
+        self.mapper.borrow_mut().ppu_address(index);
         match index {
             0x3F00 ... 0x3FFF => {
                 index &= 0x1f;
@@ -426,7 +434,7 @@ impl PPU {
             if self.sprite_attributes[i] & 0x80 == 0x80 { //flag to flip sprite vertically
                 delta = ((delta & 8) ^ if self.big_sprites {8} else {0}) | (7 - (delta & 7));
             }
-            let addr =
+            self.sprite_addresses[i] =
                 if !self.big_sprites {
                     self.sprite_pattern_base | ((oam2[i*4 + 1] as u16) << 4) | delta
                 }
@@ -435,11 +443,32 @@ impl PPU {
                     let tileno = (oam2[i*4 + 1] as u16 & 0xFE) << 4;
                     ((oam2[i*4 + 1] as u16 & 1) << 12) | tileno | delta
                 };
-            self.sprite_high_bitmaps[i] = self.contents(addr | 8);
-            self.sprite_low_bitmaps[i] = self.contents(addr);
-            if self.sprite_attributes[i] & 0x40 == 0x40 { //flag to flip horizontally
-                self.sprite_high_bitmaps[i] = bitwise_reverse(self.sprite_high_bitmaps[i]);
-                self.sprite_low_bitmaps[i] = bitwise_reverse(self.sprite_low_bitmaps[i]);
+        }
+    }
+    //one of the eight sprite pattern fetches over dots 257-320, low plane then high like the
+    //real fetch (MMC2/MMC4 latch on the high plane); empty slots fetch tile $FF, as the PPU does,
+    //so the mapper sees the same addresses
+    fn fetch_sprite(&mut self,slot: usize,high: bool) {
+        let addr = if slot < self.found {
+            self.sprite_addresses[slot]
+        } else if self.big_sprites {
+            0x1FF0
+        } else {
+            self.sprite_pattern_base | 0xFF0
+        };
+        if !high {
+            let bitmap = self.contents(addr);
+            if slot < self.found {
+                self.sprite_low_bitmaps[slot] = bitmap;
+            }
+        } else {
+            let bitmap = self.contents(addr | 8);
+            if slot < self.found {
+                self.sprite_high_bitmaps[slot] = bitmap;
+                if self.sprite_attributes[slot] & 0x40 == 0x40 { //flag to flip horizontally
+                    self.sprite_high_bitmaps[slot] = bitwise_reverse(self.sprite_high_bitmaps[slot]);
+                    self.sprite_low_bitmaps[slot] = bitwise_reverse(self.sprite_low_bitmaps[slot]);
+                }
             }
         }
     }
@@ -453,6 +482,28 @@ impl PPU {
         */
         let mut result = PPUStatus::Nothing;
         let draw = self.show_sprites || self.show_background;
+        //tell the mapper when the sprite pattern fetches start and end; sprites for the next line
+        //are picked at the start of hblank and fetched over dots 257-320
+        if draw && (self.scanline < 240 || self.scanline == 261) {
+            if self.scancycle == 257 {
+                self.mapper.borrow_mut().ppu_fetch(self.scanline,true,self.big_sprites);
+                if self.show_sprites && self.scanline < 239 {
+                    self.evaluate_sprites()
+                } else {
+                    self.found = 0;
+                }
+            } else if self.scancycle == 321 {
+                self.mapper.borrow_mut().ppu_fetch(self.scanline,false,self.big_sprites);
+            }
+            if 257 <= self.scancycle && self.scancycle <= 320 {
+                let dot = self.scancycle as usize - 257;
+                match dot % 8 {
+                    4 => self.fetch_sprite(dot / 8,false),
+                    6 => self.fetch_sprite(dot / 8,true),
+                    _ => ()
+                }
+            }
+        }
         let isfetchcycle = draw && (self.scanline == 261 || self.scanline < 240) && (0 < self.scancycle && self.scancycle <= 256 && self.scanline != 261 || 321 <= self.scancycle && self.scancycle <= 336);
         if isfetchcycle {
             if self.scanline < 240 && self.scancycle <= 256 {
@@ -535,9 +586,6 @@ impl PPU {
                 _ => ()
             }
         }
-        if self.show_sprites && self.scancycle == 321 && self.scanline < 239 {
-            self.evaluate_sprites()
-        }
         if draw {
             if self.scanline < 240 && self.scancycle == 256 {
                 //increment fine y
@@ -581,9 +629,6 @@ impl PPU {
                 }
             }
         }
-        if self.scancycle == 280 && (self.scanline == 261 || self.scanline < 240) && draw {
-            result = PPUStatus::HBlank;
-        }
         if self.scanline == 241 && self.scancycle == 1 {
             self.vblank = true;
             self.sprite_zero_hit = false;
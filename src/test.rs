@@ -11,9 +11,9 @@ mod cpu {
     impl CPU {
         pub fn test() {
             println!("Testing CPU with nestest...");
-            let ines = INES::new("roms/nestest.nes".to_string(),None);
+            let ines = INES::new("roms/nestest.nes".to_string(),None).unwrap();
             let handle = File::open("test/goodlog").unwrap();
-            let mapper = get_mapper(ines);
+            let mapper = get_mapper(ines).unwrap();
             let ppu = Rc::new(RefCell::new(PPU::new(mapper.clone())));
             let mut cpu = CPU::new(mapper,ppu);
             cpu.PC = 0xC000;
@@ -27,5 +27,236 @@ mod cpu {
             }
             println!("test passed.");
         }
+        //a CPU on an NROM board that idles after CLI, with IRQ and NMI handlers counting into $10 and $11
+        fn interrupt_cpu() -> CPU {
+            let mut rom = b"NES\x1A\x01\x01\0\0\0\0\0\0\0\0\0\0".to_vec();
+            rom.resize(16 + 0x4000 + 0x2000,0);
+            {
+                let prg = &mut rom[16..16 + 0x4000];
+                //$C000: CLI, then JMP $C001
+                prg[0..4].copy_from_slice(&[0x58,0x4C,0x01,0xC0]);
+                //$C100: on the first IRQ copy the NMI count to $12, then INC $10 and RTI
+                prg[0x100..0x10B].copy_from_slice(&[0xA5,0x10,0xD0,0x04,0xA5,0x11,0x85,0x12,0xE6,0x10,0x40]);
+                //$C200: INC $11, RTI
+                prg[0x200..0x203].copy_from_slice(&[0xE6,0x11,0x40]);
+                prg[0x3FFA..0x4000].copy_from_slice(&[0x00,0xC2,0x00,0xC0,0x00,0xC1]);
+            }
+            let mapper = get_mapper(INES::from_bytes(&rom).unwrap()).unwrap();
+            let ppu = Rc::new(RefCell::new(PPU::new(mapper.clone())));
+            let mut cpu = CPU::new(mapper,ppu);
+            for _ in 0..10 {
+                cpu.cycle();
+            }
+            cpu
+        }
+        //like Emulator::mapper_cycle with the IRQ line held high
+        fn hold_irq(&mut self,cycles: usize) {
+            for _ in 0..cycles {
+                self.cycle();
+                if let Interrupt::Null = self.interrupt {
+                    self.interrupt = Interrupt::IRQ;
+                }
+            }
+        }
+        pub fn test_interrupts() {
+            println!("Testing CPU interrupts...");
+            //each RTI unmasks the held line again, but the handler itself must not be interrupted
+            let mut cpu = CPU::interrupt_cpu();
+            cpu.hold_irq(1000);
+            let count = cpu.contents(0x10);
+            if count < 2 {
+                panic!("IRQ handler should have run again after RTI, but ran {} times",count);
+            }
+            //an NMI pending alongside the IRQ goes first, and its handler finishes before the IRQ is taken
+            let mut cpu = CPU::interrupt_cpu();
+            cpu.interrupt = Interrupt::NMI;
+            cpu.hold_irq(1000);
+            if cpu.contents(0x11) != 1 {
+                panic!("NMI handler should have run once, but ran {} times",cpu.contents(0x11));
+            }
+            if cpu.contents(0x12) != 1 || cpu.contents(0x10) < 2 {
+                panic!("IRQ handler should have run after the NMI handler");
+            }
+            println!("test passed.");
+        }
+    }
+}
+mod emulator {
+    use emulator::Emulator;
+    use ines::INES;
+    use bincode::serialize;
+    impl Emulator {
+        pub fn test() {
+            println!("Testing savestates with metroid...");
+            let ines = INES::new("roms/metroid.nes".to_string(),None).unwrap();
+            let mut emulator = Emulator::new(ines).unwrap();
+            for _ in 0..60 {
+                emulator.step_frame();
+            }
+            let state = emulator.save_state();
+            emulator.step_frame();
+            let screen = emulator.framebuffer().to_vec();
+            //start the game so the title screen tiles in CHR-RAM get replaced
+            for i in 0..240 {
+                emulator.set_controller(0,[false,false,false,i % 30 < 5,false,false,false,false]);
+                emulator.step_frame();
+            }
+            let later = emulator.save_state();
+            if later == state {
+                panic!("state didn't change between savestates");
+            }
+            emulator.load_state(&state).unwrap();
+            if emulator.save_state() != state {
+                panic!("state differs after loading a savestate");
+            }
+            //states from before the version tag, cut short, or from another rom are refused without touching anything
+            let untagged = serialize(&(vec![0u8;0x800],vec![0u8;0x100],vec![0u8;0x100],vec![0u8;0x100])).unwrap();
+            let mut foreign = state.clone();
+            foreign[4] ^= 0xFF;
+            for bad in [untagged,state[..state.len() / 2].to_vec(),foreign].iter() {
+                if emulator.load_state(bad).is_ok() {
+                    panic!("loaded a savestate that should have been refused");
+                }
+            }
+            if emulator.save_state() != state {
+                panic!("state changed after refusing a savestate");
+            }
+            emulator.step_frame();
+            if &emulator.framebuffer()[..] != &screen[..] {
+                panic!("screen differs after loading a savestate");
+            }
+            println!("test passed.");
+        }
+    }
+}
+mod mappers {
+    use mappers::{N163,get_mapper};
+    use ines::INES;
+    impl N163 {
+        pub fn test() {
+            println!("Testing N163 sound RAM auto-increment...");
+            let mut rom = b"NES\x1A\x02\x01\x30\x10\0\0\0\0\0\0\0\0".to_vec();
+            rom.resize(16 + 0x8000 + 0x2000,0);
+            let mapper = get_mapper(INES::from_bytes(&rom).unwrap()).unwrap();
+            let mut mapper = mapper.borrow_mut();
+            //128 writes go all the way around the address and end up back at 0
+            mapper.set_contents(0xF800,0x80);
+            for i in 0..0x80 {
+                mapper.set_contents(0x4800,i as u8 ^ 0x5A);
+            }
+            for i in 0..0x80 {
+                let what = mapper.contents(0x4800);
+                if what != i as u8 ^ 0x5A {
+                    panic!("sound RAM at 0x{:X} should have been: 0x{:X}, but was 0x{:X}",i,i as u8 ^ 0x5A,what);
+                }
+            }
+            println!("test passed.");
+        }
+    }
+}
+mod vrc24 {
+    use mappers::VRC24;
+    use mappers::Mapper;
+    use ines::INES;
+    impl VRC24 {
+        pub fn test() {
+            println!("Testing VRC2/VRC4 wiring...");
+            //iNES 1.0 mapper 23 and a NES 2.0 VRC2b and VRC4e
+            for &(header7,header8,vrc2,lines) in [(0x10,0x00,true,(0x05,0x0A)),(0x18,0x30,true,(0x01,0x02)),(0x18,0x20,false,(0x04,0x08))].iter() {
+                let mut rom = vec![0x4E,0x45,0x53,0x1A,0x02,0x01,0x70,header7,header8,0,0,0,0,0,0,0];
+                rom.resize(16 + 0x8000 + 0x2000,0);
+                let mut vrc24 = VRC24::new(INES::from_bytes(&rom).unwrap(),vec![]);
+                if vrc24.vrc2 != vrc2 || vrc24.register_lines != lines {
+                    panic!("mapper 23 submapper {} should have been vrc2: {} on lines {:?}, but was vrc2: {} on lines {:?}",header8 >> 4,vrc2,lines,vrc24.vrc2,vrc24.register_lines);
+                }
+            }
+            //without a submapper the IRQ registers give a VRC4 away
+            let mut rom = vec![0x4E,0x45,0x53,0x1A,0x02,0x01,0x70,0x10,0,0,0,0,0,0,0,0];
+            rom.resize(16 + 0x8000 + 0x2000,0);
+            let mut vrc24 = VRC24::new(INES::from_bytes(&rom).unwrap(),vec![]);
+            vrc24.set_contents(0xF008,0);
+            if vrc24.vrc2 {
+                panic!("mapper 23 should have turned into VRC4 after an IRQ register write");
+            }
+            println!("test passed.");
+        }
+    }
+}
+mod ines {
+    use ines::INES;
+    use mappers::get_mapper;
+    use patch;
+    use crc32fast;
+    //UPS and BPS footer: source, target and patch CRC32s, little endian
+    fn footer(mut patch: Vec<u8>,source: &[u8],target: &[u8]) -> Vec<u8> {
+        for crc in [crc32fast::hash(source),crc32fast::hash(target)].iter() {
+            patch.extend_from_slice(&[*crc as u8,(*crc >> 8) as u8,(*crc >> 16) as u8,(*crc >> 24) as u8]);
+        }
+        let crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&[crc as u8,(crc >> 8) as u8,(crc >> 16) as u8,(crc >> 24) as u8]);
+        patch
+    }
+    impl INES {
+        pub fn test_patches() {
+            println!("Testing IPS, UPS and BPS patches...");
+            let data: Vec<u8> = (0..16).collect();
+            let mut target = data.clone();
+            target[2] = 0xAA;
+            target[3] = 0xBB;
+            //a plain record, then a run past the end that grows the file
+            let ips = b"PATCH\x00\x00\x02\x00\x02\xAA\xBB\x00\x00\x10\x00\x00\x00\x03\xCCEOF";
+            let mut grown = target.clone();
+            grown.extend_from_slice(&[0xCC,0xCC,0xCC]);
+            if patch::apply(ips,&data).unwrap() != grown {
+                panic!("IPS patch gave the wrong result");
+            }
+            if patch::apply(&ips[..12],&data).is_ok() {
+                panic!("truncated IPS patch should have been refused");
+            }
+            //skip 2 bytes, then xor 2 up to the terminating zero
+            let ups = footer(vec![b'U',b'P',b'S',b'1',0x90,0x90,0x82,0xA8,0xB8,0x00],&data,&target);
+            if patch::apply(&ups,&data).unwrap() != target {
+                panic!("UPS patch gave the wrong result");
+            }
+            //source read 2, target read 2, source read 12
+            let bps = footer(vec![b'B',b'P',b'S',b'1',0x90,0x90,0x80,0x84,0x85,0xAA,0xBB,0xAC],&data,&target);
+            if patch::apply(&bps,&data).unwrap() != target {
+                panic!("BPS patch gave the wrong result");
+            }
+            //a different ROM, a corrupt patch, and a source copy past the end of the ROM
+            let mut other = data.clone();
+            other[0] = 0xFF;
+            let mut corrupt = bps.clone();
+            corrupt[9] ^= 0xFF;
+            let outside = footer(vec![b'B',b'P',b'S',b'1',0x90,0x90,0x80,0x8E,0xC0],&data,&target);
+            for &(patch,data) in [(&ups,&other),(&bps,&other),(&corrupt,&data),(&outside,&data)].iter() {
+                if patch::apply(patch,data).is_ok() {
+                    panic!("bad patch should have been refused");
+                }
+            }
+            println!("test passed.");
+        }
+        pub fn test() {
+            println!("Testing 4 KiB PRG-ROM on boards with fixed banks...");
+            for &mapper in [9,10,19,21,22,23,24,25,26,69,85].iter() {
+                //NES 2.0 exponent size: 2^12*1 bytes of PRG, then 8 KiB of CHR
+                let mut rom = vec![0x4E,0x45,0x53,0x1A,12 << 2,0x01,(mapper & 0xF) << 4,mapper & 0xF0 | 0x08,0,0x0F,0,0,0,0,0,0];
+                let prg: Vec<u8> = (0..0x1000).map(|i| (i ^ i >> 8) as u8).collect();
+                rom.extend_from_slice(&prg);
+                rom.resize(16 + 0x1000 + 0x2000,0);
+                let mapper = get_mapper(INES::from_bytes(&rom).unwrap()).unwrap();
+                let mut mapper = mapper.borrow_mut();
+                for location in 0x8000..0x10000 {
+                    mapper.contents(location as u16);
+                }
+                for location in 0xE000..0x10000 {
+                    let what = mapper.contents(location as u16);
+                    if what != prg[location & 0xFFF] {
+                        panic!("0x{:X} should have been: 0x{:X}, but was 0x{:X}",location,prg[location & 0xFFF],what);
+                    }
+                }
+            }
+            println!("test passed.");
+        }
     }
 }
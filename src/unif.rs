@@ -0,0 +1,154 @@
+use ines::INES;
+use ines::RomError;
+
+/*
+UNIF is a list of chunks after a 32 byte header:
+4 byte id, 32-bit little endian length, data.
+https://wiki.nesdev.com/w/index.php/UNIF
+*/
+
+//board name (without the NES-/HVC-/UNL-... prefix) to iNES mapper number
+fn board_mapper(board: &str) -> Option<u16> {
+    let mapper = match board {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" | "HROM" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SC1ROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM" |
+        "SJROM" | "SKROM" | "SLROM" | "SL1ROM" | "SL2ROM" | "SL3ROM" | "SLRROM" | "SNROM" |
+        "SOROM" | "SUROM" | "SXROM" | "SIROM" | "SKEPROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TLROM" | "TL1ROM" | "TL2ROM" |
+        "TNROM" | "TR1ROM" | "TSROM" | "TVROM" | "B4" | "HKROM" => 4,
+        "EKROM" | "ELROM" | "ETROM" | "EWROM" => 5,
+        "ANROM" | "AN1ROM" | "AMROM" | "AOROM" => 7,
+        "PNROM" | "PEEOROM" => 9,
+        "FJROM" | "FKROM" => 10,
+        "COLORDREAMS-74*377" => 11,
+        "BNROM" => 34,
+        "NINA-001" => 34,
+        "GNROM" | "MHROM" => 66,
+        "JLROM" | "JSROM" | "BTR" | "SUNSOFT-FME-7" => 69,
+        "UN1ROM" => 94,
+        "TLSROM" | "TKSROM" => 118,
+        "TQROM" => 119,
+        "DEROM" | "DE1ROM" | "DRROM" | "NAMCOT-3401" | "NAMCOT-3405" | "NAMCOT-3406" | "NAMCOT-3407" |
+        "NAMCOT-3413" | "NAMCOT-3414" | "NAMCOT-3415" | "NAMCOT-3416" | "NAMCOT-3417" | "NAMCOT-3451" => 206,
+        "NAMCOT-3433" | "NAMCOT-3443" => 88,
+        "NAMCOT-3425" => 95,
+        "NAMCOT-3453" => 154,
+        _ => return None
+    };
+    Some(mapper)
+}
+
+//NES 2.0 submapper for boards that share a mapper number with ones that behave differently
+fn board_submapper(board: &str) -> u8 {
+    match board {
+        "HKROM" => 1, //MMC6
+        "AMROM" => 2, //bus conflicts
+        _ => 0
+    }
+}
+
+fn strip_prefix(board: &str) -> &str {
+    for prefix in ["NES-","HVC-","UNL-","BTL-","BMC-","IREM-","KONAMI-","TENGEN-"].iter() {
+        if board.starts_with(*prefix) {
+            return &board[prefix.len()..];
+        }
+    }
+    board
+}
+
+//PRG/CHR chunks are numbered with a hex digit
+fn chunk_number(id: &[u8],prefix: &[u8]) -> Option<usize> {
+    if &id[0..3] != prefix {
+        return None;
+    }
+    (id[3] as char).to_digit(16).map(|x| x as usize)
+}
+
+pub fn parse(data: &[u8]) -> Result<INES,RomError> {
+    if data.len() < 32 || &data[0..4] != b"UNIF" {
+        return Err(RomError::BadMagic);
+    }
+    let mut board = String::new();
+    let mut prg: Vec<Vec<u8>> = vec![vec![];16];
+    let mut chr: Vec<Vec<u8>> = vec![vec![];16];
+    let mut mirroring = 5;
+    let mut battery = false;
+    let mut chr_is_ram = false;
+    let mut pos = 32;
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let len = data[pos + 4] as usize | (data[pos + 5] as usize) << 8 |
+            (data[pos + 6] as usize) << 16 | (data[pos + 7] as usize) << 24;
+        pos += 8;
+        if data.len() - pos < len {
+            return Err(if &id[0..3] == b"CHR" { RomError::TruncatedChr } else { RomError::TruncatedPrg });
+        }
+        let chunk = &data[pos..pos + len];
+        pos += len;
+        match id {
+            b"MAPR" => {
+                let end = chunk.iter().position(|&x| x == 0).unwrap_or(chunk.len());
+                board = String::from_utf8_lossy(&chunk[..end]).trim().to_string();
+            }
+            b"MIRR" if len != 0 => mirroring = chunk[0],
+            b"BATR" if len != 0 => battery = chunk[0] != 0,
+            b"VROR" if len != 0 => chr_is_ram = chunk[0] != 0,
+            _ => {
+                if let Some(n) = chunk_number(id,b"PRG") {
+                    prg[n] = chunk.to_vec();
+                } else if let Some(n) = chunk_number(id,b"CHR") {
+                    chr[n] = chunk.to_vec();
+                }
+            }
+        }
+    }
+    let name = strip_prefix(&board).to_string();
+    let mapper = match board_mapper(&name) {
+        Some(mapper) => mapper,
+        None => return Err(RomError::UnsupportedBoard(board))
+    };
+
+    let mut result = INES::empty();
+    result.mapper = mapper;
+    result.submapper = board_submapper(&name);
+    result.prgrom = prg.concat();
+    if result.prgrom.len() == 0 {
+        return Err(RomError::TruncatedPrg);
+    }
+    //boards with less than 16 KiB see it mirrored
+    while result.prgrom.len() < 0x4000 {
+        let copy = result.prgrom.clone();
+        result.prgrom.extend_from_slice(&copy);
+    }
+    result.prgrom_size = ((result.prgrom.len() + 0x3FFF) / 0x4000) as u16;
+    let chrrom = chr.concat();
+    if chrrom.len() == 0 || chr_is_ram {
+        result.chrram_size = 0x2000;
+        result.chrrom = vec![0;result.chrram_size.max(chrrom.len())];
+        result.chrrom[..chrrom.len()].copy_from_slice(&chrrom);
+    } else {
+        result.chrrom_size = ((chrrom.len() + 0x1FFF) / 0x2000) as u16;
+        result.chrrom = chrrom;
+    }
+    match mirroring {
+        0 => result.vertical_mirroring = false,
+        1 => result.vertical_mirroring = true,
+        4 => result.four_screen = true,
+        _ => () //single screen or mapper controlled
+    }
+    let ram = match name.as_str() {
+        "SOROM" => 0x4000,
+        "SXROM" => 0x8000,
+        _ => 0x2000
+    };
+    result.persistent_memory = battery;
+    if battery {
+        result.prgnvram_size = ram;
+    } else {
+        result.prgram_size = ram;
+    }
+    result.savedata = vec![0;ram];
+    Ok(result)
+}